@@ -0,0 +1,137 @@
+//! CSR 指令解码。
+//!
+//! SEE 用它模拟特权软件不能直接访问的 CSR，解码本身不访问硬件。
+
+/// CSR 指令的操作。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Op {
+    /// csrrw/csrrwi
+    Write,
+    /// csrrs/csrrsi
+    Set,
+    /// csrrc/csrrci
+    Clear,
+}
+
+/// CSR 指令的源操作数。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Src {
+    Reg(usize),
+    Imm(usize),
+}
+
+/// 解码的 CSR 指令。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CsrInsn {
+    pub csr: usize,
+    pub rd: usize,
+    pub op: Op,
+    pub src: Src,
+}
+
+impl CsrInsn {
+    /// 指令是否写 CSR。
+    ///
+    /// csrrs/csrrc 的源操作数是 `x0` 或 0 时只读。
+    #[inline]
+    pub fn writes(&self) -> bool {
+        self.op == Op::Write || !matches!(self.src, Src::Reg(0) | Src::Imm(0))
+    }
+}
+
+/// 解码 CSR 指令。
+pub fn decode(insn: usize) -> Option<CsrInsn> {
+    const SYSTEM: usize = 0x73;
+    if insn & 0x7f != SYSTEM {
+        return None;
+    }
+    let rs1 = (insn >> 15) & 0x1f;
+    let (op, src) = match (insn >> 12) & 0b111 {
+        0b001 => (Op::Write, Src::Reg(rs1)),
+        0b010 => (Op::Set, Src::Reg(rs1)),
+        0b011 => (Op::Clear, Src::Reg(rs1)),
+        0b101 => (Op::Write, Src::Imm(rs1)),
+        0b110 => (Op::Set, Src::Imm(rs1)),
+        0b111 => (Op::Clear, Src::Imm(rs1)),
+        _ => return None,
+    };
+    Some(CsrInsn {
+        csr: (insn >> 20) & 0xfff,
+        rd: (insn >> 7) & 0x1f,
+        op,
+        src,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 按指令格式拼出 CSR 指令。
+    fn encode(csr: usize, rs1: usize, funct3: usize, rd: usize) -> usize {
+        csr << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | 0x73
+    }
+
+    #[test]
+    fn rdtime() {
+        // csrrs a0, time, zero
+        let insn = decode(0xc0102573).unwrap();
+        assert_eq!(
+            insn,
+            CsrInsn {
+                csr: 0xc01,
+                rd: 10,
+                op: Op::Set,
+                src: Src::Reg(0),
+            }
+        );
+        assert!(!insn.writes());
+    }
+
+    #[test]
+    fn register_forms() {
+        for (funct3, op) in [(0b001, Op::Write), (0b010, Op::Set), (0b011, Op::Clear)] {
+            let insn = decode(encode(0x7c0, 5, funct3, 6)).unwrap();
+            assert_eq!(insn.csr, 0x7c0);
+            assert_eq!(insn.rd, 6);
+            assert_eq!(insn.op, op);
+            assert_eq!(insn.src, Src::Reg(5));
+            assert!(insn.writes());
+        }
+    }
+
+    #[test]
+    fn immediate_forms() {
+        for (funct3, op) in [(0b101, Op::Write), (0b110, Op::Set), (0b111, Op::Clear)] {
+            let insn = decode(encode(0xfc0, 31, funct3, 1)).unwrap();
+            assert_eq!(insn.csr, 0xfc0);
+            assert_eq!(insn.rd, 1);
+            assert_eq!(insn.op, op);
+            assert_eq!(insn.src, Src::Imm(31));
+            assert!(insn.writes());
+        }
+    }
+
+    #[test]
+    fn read_only_forms() {
+        // csrrs/csrrc 源操作数为 0 时只读，csrrw/csrrwi 总是写
+        assert!(!decode(encode(0xc01, 0, 0b010, 1)).unwrap().writes());
+        assert!(!decode(encode(0xc01, 0, 0b011, 1)).unwrap().writes());
+        assert!(!decode(encode(0xc01, 0, 0b110, 1)).unwrap().writes());
+        assert!(!decode(encode(0xc01, 0, 0b111, 1)).unwrap().writes());
+        assert!(decode(encode(0xc01, 0, 0b001, 0)).unwrap().writes());
+        assert!(decode(encode(0xc01, 0, 0b101, 0)).unwrap().writes());
+    }
+
+    #[test]
+    fn not_csr() {
+        // ecall、ebreak、wfi 的 funct3 是 0
+        assert_eq!(decode(0x00000073), None);
+        assert_eq!(decode(0x00100073), None);
+        assert_eq!(decode(0x10500073), None);
+        // funct3 = 0b100 保留
+        assert_eq!(decode(encode(0xc01, 0, 0b100, 1)), None);
+        // addi a0, a0, 1
+        assert_eq!(decode(0x00150513), None);
+    }
+}
@@ -0,0 +1,228 @@
+//! 解析 RISC-V ELF64 可执行文件。
+//!
+//! 只读取加载需要的信息：入口、可加载段和文件的范围。
+
+use core::ops::Range;
+
+const ELF_MAGIC: [u8; 4] = *b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_RISCV: u16 = 243;
+const PT_LOAD: u32 = 1;
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+
+/// ELF 解析错误。
+#[derive(Debug)]
+pub enum Error {
+    /// 不是 ELF 文件。
+    BadMagic,
+    /// 不是 RISC-V 64 位小端的可执行文件。
+    Unsupported,
+    /// 文件头指向缓冲区之外。
+    Truncated,
+    /// 段或节头表的范围超出地址空间。
+    Overflow,
+}
+
+/// 可加载段。
+#[derive(Clone, Debug)]
+pub struct Segment {
+    /// 段在文件中的位置。
+    pub file: Range<usize>,
+    /// 段的物理地址。
+    pub paddr: usize,
+    /// 段在内存中的大小，超出文件部分的要清零。
+    pub memsz: usize,
+}
+
+impl Segment {
+    /// 段在内存中的范围。
+    #[inline]
+    pub fn memory(&self) -> Range<usize> {
+        self.paddr..self.paddr + self.memsz
+    }
+}
+
+/// ELF 可执行文件。
+pub struct Elf<'a>(&'a [u8]);
+
+impl<'a> Elf<'a> {
+    /// 解析缓冲区开头的 ELF 文件，缓冲区可以比文件长。
+    pub fn parse(buf: &'a [u8]) -> Result<Self, Error> {
+        if buf.len() < EHDR_SIZE || buf[..4] != ELF_MAGIC {
+            return Err(Error::BadMagic);
+        }
+        let elf = Self(buf);
+        if buf[4] != ELFCLASS64
+            || buf[5] != ELFDATA2LSB
+            || elf.u16(0x10) != ET_EXEC
+            || elf.u16(0x12) != EM_RISCV
+            || elf.u16(0x36) as usize != PHDR_SIZE
+        {
+            return Err(Error::Unsupported);
+        }
+        // 之后直接计算这些范围，这里先检查它们不会溢出
+        let phdrs_end = elf
+            .phoff()
+            .checked_add(elf.phnum() * PHDR_SIZE)
+            .ok_or(Error::Overflow)?;
+        if phdrs_end > buf.len() {
+            return Err(Error::Truncated);
+        }
+        for ph in elf.load_headers() {
+            let file_end = (elf.u64(ph + 0x08) as usize)
+                .checked_add(elf.u64(ph + 0x20) as usize)
+                .ok_or(Error::Overflow)?;
+            (elf.u64(ph + 0x18) as usize)
+                .checked_add(elf.u64(ph + 0x28) as usize)
+                .ok_or(Error::Overflow)?;
+            if file_end > buf.len() {
+                return Err(Error::Truncated);
+            }
+        }
+        (elf.u64(0x28) as usize)
+            .checked_add(elf.u16(0x3c) as usize * elf.u16(0x3a) as usize)
+            .ok_or(Error::Overflow)?;
+        Ok(elf)
+    }
+
+    /// 入口地址。
+    #[inline]
+    pub fn entry(&self) -> usize {
+        self.u64(0x18) as _
+    }
+
+    /// 文件的长度，包括段、程序头和节头。
+    pub fn file_size(&self) -> usize {
+        let shdrs = self.u64(0x28) as usize + self.u16(0x3c) as usize * self.u16(0x3a) as usize;
+        self.segments().map(|s| s.file.end).fold(
+            shdrs.max(self.phoff() + self.phnum() * PHDR_SIZE),
+            usize::max,
+        )
+    }
+
+    /// 可加载段。
+    pub fn segments(&self) -> impl Iterator<Item = Segment> + '_ {
+        self.load_headers().map(|ph| {
+            let offset = self.u64(ph + 0x08) as usize;
+            Segment {
+                file: offset..offset + self.u64(ph + 0x20) as usize,
+                paddr: self.u64(ph + 0x18) as _,
+                memsz: self.u64(ph + 0x28) as _,
+            }
+        })
+    }
+
+    /// 所有可加载段在内存中占用的范围。
+    pub fn memory(&self) -> Option<Range<usize>> {
+        self.segments()
+            .map(|s| s.memory())
+            .reduce(|a, b| a.start.min(b.start)..a.end.max(b.end))
+    }
+
+    /// 可加载段的程序头的位置。
+    #[inline]
+    fn load_headers(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.phnum())
+            .map(|i| self.phoff() + i * PHDR_SIZE)
+            .filter(|ph| self.u32(*ph) == PT_LOAD)
+    }
+
+    #[inline]
+    fn phoff(&self) -> usize {
+        self.u64(0x20) as _
+    }
+
+    #[inline]
+    fn phnum(&self) -> usize {
+        self.u16(0x38) as _
+    }
+
+    #[inline]
+    fn u16(&self, off: usize) -> u16 {
+        u16::from_le_bytes([self.0[off], self.0[off + 1]])
+    }
+
+    #[inline]
+    fn u32(&self, off: usize) -> u32 {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&self.0[off..off + 4]);
+        u32::from_le_bytes(bytes)
+    }
+
+    #[inline]
+    fn u64(&self, off: usize) -> u64 {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&self.0[off..off + 8]);
+        u64::from_le_bytes(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 只有一个可加载段的 ELF 文件头和程序头。
+    fn elf(offset: u64, filesz: u64, paddr: u64, memsz: u64) -> [u8; EHDR_SIZE + PHDR_SIZE] {
+        let mut buf = [0u8; EHDR_SIZE + PHDR_SIZE];
+        buf[..4].copy_from_slice(&ELF_MAGIC);
+        buf[4] = ELFCLASS64;
+        buf[5] = ELFDATA2LSB;
+        buf[0x10..0x12].copy_from_slice(&ET_EXEC.to_le_bytes());
+        buf[0x12..0x14].copy_from_slice(&EM_RISCV.to_le_bytes());
+        buf[0x18..0x20].copy_from_slice(&0x8020_0000u64.to_le_bytes());
+        buf[0x20..0x28].copy_from_slice(&(EHDR_SIZE as u64).to_le_bytes());
+        buf[0x36..0x38].copy_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+        buf[0x38..0x3a].copy_from_slice(&1u16.to_le_bytes());
+        let ph = &mut buf[EHDR_SIZE..];
+        ph[..4].copy_from_slice(&PT_LOAD.to_le_bytes());
+        ph[0x08..0x10].copy_from_slice(&offset.to_le_bytes());
+        ph[0x18..0x20].copy_from_slice(&paddr.to_le_bytes());
+        ph[0x20..0x28].copy_from_slice(&filesz.to_le_bytes());
+        ph[0x28..0x30].copy_from_slice(&memsz.to_le_bytes());
+        buf
+    }
+
+    #[test]
+    fn parse_segment() {
+        let buf = elf(0, 0x40, 0x8020_0000, 0x1000);
+        let elf = Elf::parse(&buf).unwrap();
+        assert_eq!(elf.entry(), 0x8020_0000);
+        assert_eq!(elf.memory(), Some(0x8020_0000..0x8020_1000));
+        assert_eq!(elf.file_size(), EHDR_SIZE + PHDR_SIZE);
+    }
+
+    #[test]
+    fn truncated_segment() {
+        let buf = elf(0x40, 0x1000, 0x8020_0000, 0x1000);
+        assert!(matches!(Elf::parse(&buf), Err(Error::Truncated)));
+    }
+
+    #[test]
+    fn file_range_overflow() {
+        let buf = elf(u64::MAX, 2, 0x8020_0000, 0x1000);
+        assert!(matches!(Elf::parse(&buf), Err(Error::Overflow)));
+    }
+
+    #[test]
+    fn memory_range_overflow() {
+        let buf = elf(0, 0x40, u64::MAX - 0xfff, 0x1000);
+        assert!(matches!(Elf::parse(&buf), Err(Error::Overflow)));
+    }
+
+    #[test]
+    fn header_table_overflow() {
+        let mut buf = elf(0, 0x40, 0x8020_0000, 0x1000);
+        buf[0x20..0x28].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(Elf::parse(&buf), Err(Error::Overflow)));
+
+        let mut buf = elf(0, 0x40, 0x8020_0000, 0x1000);
+        buf[0x28..0x30].copy_from_slice(&u64::MAX.to_le_bytes());
+        buf[0x3a..0x3c].copy_from_slice(&64u16.to_le_bytes());
+        buf[0x3c..0x3e].copy_from_slice(&1u16.to_le_bytes());
+        assert!(matches!(Elf::parse(&buf), Err(Error::Overflow)));
+    }
+}
@@ -0,0 +1,353 @@
+//! 原地修改扁平设备树。
+//!
+//! 只支持 dtc 生成的布局：内存保留表、结构块、字符串块依次排列，字符串块位于末尾。
+//! 节点用它在结构块中的偏移表示，插入数据会移动之后的节点，修改后应按路径重新查找。
+
+use core::ops::Range;
+
+const MAGIC: u32 = 0xd00d_feed;
+
+const TOTAL_SIZE: usize = 0x04;
+const OFF_DT_STRUCT: usize = 0x08;
+const OFF_DT_STRINGS: usize = 0x0c;
+const OFF_MEM_RSVMAP: usize = 0x10;
+const VERSION: usize = 0x14;
+const SIZE_DT_STRINGS: usize = 0x20;
+const SIZE_DT_STRUCT: usize = 0x24;
+const HEADER_SIZE: usize = 0x28;
+
+const BEGIN_NODE: u32 = 1;
+const END_NODE: u32 = 2;
+const PROP: u32 = 3;
+const NOP: u32 = 4;
+
+/// 设备树修改错误。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// 不是设备树。
+    BadMagic,
+    /// 版本低于 17。
+    BadVersion,
+    /// 不支持的布局。
+    BadLayout,
+    /// 设备树所在的空间不够。
+    NoSpace,
+    /// 找不到节点。
+    NotFound,
+    /// 不支持的 `#address-cells` 或 `#size-cells`。
+    BadCells,
+}
+
+/// 可修改的扁平设备树。
+pub struct Fdt<'a> {
+    buf: &'a mut [u8],
+}
+
+impl<'a> Fdt<'a> {
+    /// 从缓冲区构造，缓冲区开头是设备树，之后的空间都可以用来扩展设备树。
+    pub fn new(buf: &'a mut [u8]) -> Result<Self, Error> {
+        if buf.len() < HEADER_SIZE {
+            return Err(Error::BadMagic);
+        }
+        let fdt = Self { buf };
+        if fdt.header(0) != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        if fdt.header(VERSION) < 17 {
+            return Err(Error::BadVersion);
+        }
+        // 之后按头中的偏移直接访问缓冲区，这里先检查各部分都在设备树之内
+        let total = fdt.total_size();
+        let block = |off, size| {
+            let start = fdt.header(off) as usize;
+            start
+                .checked_add(fdt.header(size) as usize)
+                .filter(|end| *end <= total)
+                .map(|end| start..end)
+        };
+        let rsvmap = fdt.header(OFF_MEM_RSVMAP) as usize;
+        let (Some(structure), Some(strings)) = (
+            block(OFF_DT_STRUCT, SIZE_DT_STRUCT),
+            block(OFF_DT_STRINGS, SIZE_DT_STRINGS),
+        ) else {
+            return Err(Error::BadLayout);
+        };
+        // 内存保留表至少有一个结束项
+        if total > fdt.buf.len()
+            || rsvmap < HEADER_SIZE
+            || rsvmap + 16 > structure.start
+            || structure.end > strings.start
+            || strings.end != total
+        {
+            return Err(Error::BadLayout);
+        }
+        Ok(fdt)
+    }
+
+    /// 从地址构造。
+    ///
+    /// # Safety
+    ///
+    /// `addr` 之后的 `window` 字节必须可写且不被其他对象使用。
+    #[inline]
+    pub unsafe fn from_raw_parts(addr: usize, window: usize) -> Result<Self, Error> {
+        Self::new(core::slice::from_raw_parts_mut(addr as *mut u8, window))
+    }
+
+    /// 设备树的总大小。
+    #[inline]
+    pub fn total_size(&self) -> usize {
+        self.header(TOTAL_SIZE) as _
+    }
+
+    /// 查找节点，路径中省略单元地址的部分匹配任意单元地址。
+    pub fn find_node(&self, path: &str) -> Option<usize> {
+        let mut node = self.skip_nop(0);
+        if self.word(node) != BEGIN_NODE {
+            return None;
+        }
+        for name in path.split('/').filter(|s| !s.is_empty()) {
+            node = self
+                .children(node)
+                .find(|&child| name_matches(self.node_name(child), name.as_bytes()))?;
+        }
+        Some(node)
+    }
+
+    /// 读节点的属性值。
+    pub fn property(&self, node: usize, name: &str) -> Option<&[u8]> {
+        let prop = self.find_property(node, name)?;
+        let len = self.word(prop + 4) as usize;
+        let start = self.structure().start + prop + 12;
+        Some(&self.buf[start..start + len])
+    }
+
+    /// 读节点的一个 u32 属性。
+    pub fn property_u32(&self, node: usize, name: &str) -> Option<u32> {
+        self.property(node, name)
+            .filter(|v| v.len() == 4)
+            .map(|v| u32::from_be_bytes([v[0], v[1], v[2], v[3]]))
+    }
+
+    /// 设置节点的属性，没有则添加。
+    pub fn set_property(&mut self, node: usize, name: &str, value: &[u8]) -> Result<(), Error> {
+        let (at, remove) = match self.find_property(node, name) {
+            Some(prop) => (prop, align4(12 + self.word(prop + 4) as usize)),
+            None => (self.body(node), 0),
+        };
+        let name_off = self.string(name)?;
+        let start = self.make_room(at, remove, align4(12 + value.len()))?;
+        let buf = &mut self.buf[start..];
+        buf[0..4].copy_from_slice(&PROP.to_be_bytes());
+        buf[4..8].copy_from_slice(&(value.len() as u32).to_be_bytes());
+        buf[8..12].copy_from_slice(&(name_off as u32).to_be_bytes());
+        buf[12..][..value.len()].copy_from_slice(value);
+        buf[12 + value.len()..align4(12 + value.len())].fill(0);
+        Ok(())
+    }
+
+    /// 删除节点的属性，属性不存在时什么都不做。
+    pub fn remove_property(&mut self, node: usize, name: &str) -> Result<(), Error> {
+        if let Some(prop) = self.find_property(node, name) {
+            let len = align4(12 + self.word(prop + 4) as usize);
+            self.make_room(prop, len, 0)?;
+        }
+        Ok(())
+    }
+
+    /// 在节点末尾添加空的子节点，返回子节点。
+    pub fn add_subnode(&mut self, parent: usize, name: &str) -> Result<usize, Error> {
+        // 插入到父节点的 END_NODE 之前
+        let at = self.skip_node(parent) - 4;
+        let len = align4(4 + name.len() + 1) + 4;
+        let start = self.make_room(at, 0, len)?;
+        let buf = &mut self.buf[start..][..len];
+        buf.fill(0);
+        buf[0..4].copy_from_slice(&BEGIN_NODE.to_be_bytes());
+        buf[4..][..name.len()].copy_from_slice(name.as_bytes());
+        buf[len - 4..].copy_from_slice(&END_NODE.to_be_bytes());
+        Ok(at)
+    }
+
+    /// 查找节点，没有则在父节点中添加。
+    pub fn find_or_add_node(&mut self, parent: &str, name: &str) -> Result<usize, Error> {
+        let parent_node = self.find_node(parent).ok_or(Error::NotFound)?;
+        let found = self
+            .children(parent_node)
+            .find(|&child| name_matches(self.node_name(child), name.as_bytes()));
+        match found {
+            Some(node) => Ok(node),
+            None => self.add_subnode(parent_node, name),
+        }
+    }
+
+    #[inline]
+    fn header(&self, off: usize) -> u32 {
+        read_be(self.buf, off)
+    }
+
+    #[inline]
+    fn set_header(&mut self, off: usize, val: usize) {
+        self.buf[off..off + 4].copy_from_slice(&(val as u32).to_be_bytes());
+    }
+
+    #[inline]
+    fn structure(&self) -> Range<usize> {
+        let start = self.header(OFF_DT_STRUCT) as usize;
+        start..start + self.header(SIZE_DT_STRUCT) as usize
+    }
+
+    #[inline]
+    fn strings(&self) -> Range<usize> {
+        let start = self.header(OFF_DT_STRINGS) as usize;
+        start..start + self.header(SIZE_DT_STRINGS) as usize
+    }
+
+    /// 结构块中 `off` 处的字。
+    #[inline]
+    fn word(&self, off: usize) -> u32 {
+        read_be(self.buf, self.structure().start + off)
+    }
+
+    /// `off` 处的记号之后的位置。
+    fn next(&self, off: usize) -> usize {
+        match self.word(off) {
+            BEGIN_NODE => align4(off + 4 + self.node_name(off).len() + 1),
+            PROP => align4(off + 12 + self.word(off + 4) as usize),
+            _ => off + 4,
+        }
+    }
+
+    #[inline]
+    fn skip_nop(&self, mut off: usize) -> usize {
+        while self.word(off) == NOP {
+            off += 4;
+        }
+        off
+    }
+
+    fn node_name(&self, node: usize) -> &[u8] {
+        let start = self.structure().start + node + 4;
+        let len = self.buf[start..].iter().position(|c| *c == 0).unwrap_or(0);
+        &self.buf[start..start + len]
+    }
+
+    /// 节点属性之后，即第一个子节点或 END_NODE 的位置。
+    fn body(&self, node: usize) -> usize {
+        let mut off = self.next(node);
+        while matches!(self.word(off), PROP | NOP) {
+            off = self.next(off);
+        }
+        off
+    }
+
+    /// 节点的 END_NODE 之后的位置。
+    fn skip_node(&self, node: usize) -> usize {
+        let mut depth = 0usize;
+        let mut off = node;
+        loop {
+            match self.word(off) {
+                BEGIN_NODE => depth += 1,
+                END_NODE => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return off + 4;
+                    }
+                }
+                _ => {}
+            }
+            off = self.next(off);
+        }
+    }
+
+    fn children(&self, node: usize) -> impl Iterator<Item = usize> + '_ {
+        let mut off = self.body(node);
+        core::iter::from_fn(move || {
+            off = self.skip_nop(off);
+            if self.word(off) == BEGIN_NODE {
+                let child = off;
+                off = self.skip_node(off);
+                Some(child)
+            } else {
+                None
+            }
+        })
+    }
+
+    fn find_property(&self, node: usize, name: &str) -> Option<usize> {
+        let strings = self.strings();
+        let mut off = self.next(node);
+        loop {
+            match self.word(off) {
+                PROP => {
+                    let name_off = strings.start + self.word(off + 8) as usize;
+                    let prop_name = &self.buf[name_off..strings.end];
+                    let len = prop_name.iter().position(|c| *c == 0)?;
+                    if &prop_name[..len] == name.as_bytes() {
+                        return Some(off);
+                    }
+                }
+                NOP => {}
+                _ => return None,
+            }
+            off = self.next(off);
+        }
+    }
+
+    /// 查找字符串块中的字符串，没有则添加到末尾，返回它在字符串块中的偏移。
+    fn string(&mut self, name: &str) -> Result<usize, Error> {
+        let strings = self.strings();
+        let mut off = 0;
+        for s in self.buf[strings.clone()].split(|c| *c == 0) {
+            if s == name.as_bytes() && strings.start + off + s.len() < strings.end {
+                return Ok(off);
+            }
+            off += s.len() + 1;
+        }
+        let total = self.total_size();
+        let len = name.len() + 1;
+        if total + len > self.buf.len() {
+            return Err(Error::NoSpace);
+        }
+        self.buf[total..total + name.len()].copy_from_slice(name.as_bytes());
+        self.buf[total + name.len()] = 0;
+        self.set_header(SIZE_DT_STRINGS, strings.len() + len);
+        self.set_header(TOTAL_SIZE, total + len);
+        Ok(strings.len())
+    }
+
+    /// 把结构块中 `at` 处的 `remove` 字节替换为 `insert` 字节的空间，返回空间在缓冲区中的位置。
+    fn make_room(&mut self, at: usize, remove: usize, insert: usize) -> Result<usize, Error> {
+        let total = self.total_size();
+        let new_total = total - remove + insert;
+        if new_total > self.buf.len() {
+            return Err(Error::NoSpace);
+        }
+        let start = self.structure().start + at;
+        self.buf.copy_within(start + remove..total, start + insert);
+        let structure = self.structure();
+        let strings = self.strings();
+        self.set_header(SIZE_DT_STRUCT, structure.len() - remove + insert);
+        self.set_header(OFF_DT_STRINGS, strings.start - remove + insert);
+        self.set_header(TOTAL_SIZE, new_total);
+        Ok(start)
+    }
+}
+
+/// 节点名匹配路径中的一节，路径中没有单元地址时忽略节点名的单元地址。
+fn name_matches(node: &[u8], name: &[u8]) -> bool {
+    if node == name {
+        return true;
+    }
+    !name.contains(&b'@') && node.split(|c| *c == b'@').next() == Some(name)
+}
+
+#[inline]
+fn read_be(buf: &[u8], off: usize) -> u32 {
+    u32::from_be_bytes([buf[off], buf[off + 1], buf[off + 2], buf[off + 3]])
+}
+
+#[inline]
+const fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
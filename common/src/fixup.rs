@@ -0,0 +1,409 @@
+//! 启动特权软件前修改设备树。
+//!
+//! 修改分为几个阶段依次执行，一个阶段失败不影响之后的阶段。
+
+use crate::fdt::{Error, Fdt};
+use core::{ffi::CStr, fmt::Write, ops::Range};
+
+/// 修改设备树需要的信息。
+pub struct Params<'a> {
+    /// 固件占用的主存。
+    pub firmware: Range<usize>,
+    /// SPL 探测到的主存。
+    pub memory: Option<Range<usize>>,
+    /// `time` 的频率。
+    pub timebase: u32,
+    /// 覆盖设备树的启动参数。
+    pub bootargs: Option<&'a CStr>,
+    /// 初始内存盘。
+    pub initrd: Option<Range<usize>>,
+}
+
+type Stage = fn(&mut Fdt, &Params) -> Result<(), Error>;
+
+const STAGES: [(&str, Stage); 5] = [
+    ("memory", memory),
+    ("timebase", timebase),
+    ("reserved-memory", reserve_firmware),
+    ("chosen", chosen),
+    ("initrd", initrd),
+];
+
+/// 依次执行所有阶段，失败的阶段交给 `report`。
+pub fn apply(fdt: &mut Fdt, params: &Params, mut report: impl FnMut(&str, Error)) {
+    for (name, stage) in STAGES {
+        if let Err(e) = stage(fdt, params) {
+            report(name, e);
+        }
+    }
+}
+
+/// 把 `/memory` 改为探测到的主存。
+fn memory(fdt: &mut Fdt, params: &Params) -> Result<(), Error> {
+    let Some(range) = params.memory.clone() else {
+        return Ok(());
+    };
+    let (address_cells, size_cells) = cells(fdt, "/");
+    let node = fdt.find_node("/memory").ok_or(Error::NotFound)?;
+    let mut reg = [0u8; 16];
+    let len = encode_cells(&mut reg, range.start, address_cells)?;
+    let len = len + encode_cells(&mut reg[len..], range.len(), size_cells)?;
+    fdt.set_property(node, "reg", &reg[..len])
+}
+
+/// 设置 `/cpus/timebase-frequency`。
+fn timebase(fdt: &mut Fdt, params: &Params) -> Result<(), Error> {
+    let node = fdt.find_node("/cpus").ok_or(Error::NotFound)?;
+    fdt.set_property(node, "timebase-frequency", &params.timebase.to_be_bytes())
+}
+
+/// 保留固件占用的主存。
+fn reserve_firmware(fdt: &mut Fdt, params: &Params) -> Result<(), Error> {
+    reserve(fdt, "mmode_resv0", params.firmware.clone())
+}
+
+/// 在 `/reserved-memory` 中添加不映射的保留区域。
+///
+/// 没有 `/reserved-memory` 时按根节点的单元数创建。先编码 `reg`，单元数不支持时不修改设备树。
+fn reserve(fdt: &mut Fdt, name: &str, range: Range<usize>) -> Result<(), Error> {
+    let exists = fdt.find_node("/reserved-memory").is_some();
+    let (address_cells, size_cells) = cells(fdt, if exists { "/reserved-memory" } else { "/" });
+    let mut reg = [0u8; 16];
+    let len = encode_cells(&mut reg, range.start, address_cells)?;
+    let len = len + encode_cells(&mut reg[len..], range.len(), size_cells)?;
+    if !exists {
+        let root = fdt.find_node("/").ok_or(Error::NotFound)?;
+        let node = fdt.add_subnode(root, "reserved-memory")?;
+        fdt.set_property(node, "#address-cells", &address_cells.to_be_bytes())?;
+        fdt.set_property(node, "#size-cells", &size_cells.to_be_bytes())?;
+        fdt.set_property(node, "ranges", &[])?;
+    }
+
+    let mut node_name = NodeName([0u8; 32], 0);
+    write!(node_name, "{name}@{:x}", range.start).map_err(|_| Error::NoSpace)?;
+    let node = fdt.find_or_add_node("/reserved-memory", node_name.as_str())?;
+    fdt.set_property(node, "reg", &reg[..len])?;
+    fdt.set_property(node, "no-map", &[])
+}
+
+/// 确保 `/chosen` 存在并指定了控制台，用启动参数覆盖设备树中的。
+fn chosen(fdt: &mut Fdt, params: &Params) -> Result<(), Error> {
+    let node = fdt.find_or_add_node("/", "chosen")?;
+    if fdt.property(node, "stdout-path").is_none() {
+        fdt.set_property(node, "stdout-path", b"serial0:115200n8\0")?;
+    }
+    if let Some(bootargs) = params.bootargs {
+        fdt.set_property(node, "bootargs", bootargs.to_bytes_with_nul())?;
+    }
+    Ok(())
+}
+
+/// 在 `/chosen` 中记录初始内存盘的位置。
+fn initrd(fdt: &mut Fdt, params: &Params) -> Result<(), Error> {
+    let node = fdt.find_or_add_node("/", "chosen")?;
+    match params.initrd.clone() {
+        Some(range) => {
+            fdt.set_property(
+                node,
+                "linux,initrd-start",
+                &(range.start as u64).to_be_bytes(),
+            )?;
+            fdt.set_property(node, "linux,initrd-end", &(range.end as u64).to_be_bytes())
+        }
+        // 删除设备树中可能过时的位置
+        None => {
+            fdt.remove_property(node, "linux,initrd-start")?;
+            fdt.remove_property(node, "linux,initrd-end")
+        }
+    }
+}
+
+/// 节点的 `#address-cells` 和 `#size-cells`。
+fn cells(fdt: &Fdt, path: &str) -> (u32, u32) {
+    let node = fdt.find_node(path);
+    let get = |name, default| {
+        node.and_then(|node| fdt.property_u32(node, name))
+            .unwrap_or(default)
+    };
+    (get("#address-cells", 2), get("#size-cells", 1))
+}
+
+/// 按单元数编码地址或大小，返回编码的长度。
+///
+/// 只支持 1 个或 2 个单元。
+fn encode_cells(buf: &mut [u8], val: usize, cells: u32) -> Result<usize, Error> {
+    match cells {
+        1 => {
+            buf[..4].copy_from_slice(&(val as u32).to_be_bytes());
+            Ok(4)
+        }
+        2 => {
+            buf[..8].copy_from_slice(&(val as u64).to_be_bytes());
+            Ok(8)
+        }
+        _ => Err(Error::BadCells),
+    }
+}
+
+/// 节点名的缓冲区。
+struct NodeName([u8; 32], usize);
+
+impl NodeName {
+    #[inline]
+    fn as_str(&self) -> &str {
+        unsafe { core::str::from_utf8_unchecked(&self.0[..self.1]) }
+    }
+}
+
+impl Write for NodeName {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let end = self.1 + s.len();
+        if end > self.0.len() {
+            return Err(core::fmt::Error);
+        }
+        self.0[self.1..end].copy_from_slice(s.as_bytes());
+        self.1 = end;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dtb_walker::{Dtb, DtbObj, HeaderError::*, Property, WalkOperation::*};
+    use std::{
+        io::Write as _,
+        process::{Command, Stdio},
+    };
+
+    /// 用 dtc 编译设备树源码，留出扩展的空间。
+    fn dtc(source: &[u8]) -> Vec<u8> {
+        let mut child = Command::new("dtc")
+            .args(["-I", "dts", "-O", "dtb", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("dtc is required to run fdt tests");
+        child.stdin.take().unwrap().write_all(source).unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success(), "dtc failed");
+        let mut buf = output.stdout;
+        buf.resize(buf.len() + 4096, 0);
+        buf
+    }
+
+    fn nezha() -> Vec<u8> {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../nezha.dts");
+        dtc(&std::fs::read(path).unwrap())
+    }
+
+    const PARAMS: Params = Params {
+        firmware: 0x4000_0000..0x4020_0000,
+        memory: None,
+        timebase: 24_000_000,
+        bootargs: None,
+        initrd: None,
+    };
+
+    /// `/reserved-memory` 的子节点：`(节点名, reg, no-map)`，`reg` 展开为起止地址。
+    type Reserved = (String, Vec<(usize, usize)>, bool);
+
+    /// 用 dtb-walker 重新解析，收集 `/reserved-memory` 的子节点和 `/chosen` 的属性。
+    #[derive(Default, Debug)]
+    struct Parsed {
+        reserved: Vec<Reserved>,
+        /// `(属性名, 值)`
+        chosen: Vec<(String, Vec<u8>)>,
+    }
+
+    fn parse(buf: &[u8]) -> Parsed {
+        let dtb = unsafe {
+            Dtb::from_raw_parts_filtered(buf.as_ptr(), |e| matches!(e, LastCompVersion(16)))
+        }
+        .unwrap();
+        let mut ans = Parsed::default();
+        dtb.walk(|path, obj| match obj {
+            DtbObj::SubNode { name } => {
+                if path.is_root() {
+                    if name.as_bytes() == b"reserved-memory" || name.as_bytes() == b"chosen" {
+                        StepInto
+                    } else {
+                        StepOver
+                    }
+                } else if path.name().as_bytes() == b"reserved-memory" {
+                    let name = String::from_utf8(name.as_bytes().to_vec()).unwrap();
+                    ans.reserved.push((name, Vec::new(), false));
+                    StepInto
+                } else {
+                    StepOver
+                }
+            }
+            DtbObj::Property(Property::Reg(reg)) if !path.is_root() => {
+                if let Some(last) = ans.reserved.last_mut() {
+                    last.1.extend(reg.map(|r| (r.start, r.end)));
+                }
+                StepOver
+            }
+            DtbObj::Property(Property::General { name, value }) => {
+                let name = String::from_utf8(name.as_bytes().to_vec()).unwrap();
+                if path.name().as_bytes() == b"chosen" {
+                    ans.chosen.push((name, value.to_vec()));
+                } else if name == "no-map" {
+                    if let Some(last) = ans.reserved.last_mut() {
+                        last.2 = true;
+                    }
+                }
+                StepOver
+            }
+            DtbObj::Property(_) => StepOver,
+        });
+        ans
+    }
+
+    fn apply_ok(buf: &mut [u8], params: &Params) {
+        let mut fdt = Fdt::new(buf).unwrap();
+        apply(&mut fdt, params, |name, e| {
+            panic!("stage {name} failed: {e:?}")
+        });
+    }
+
+    #[test]
+    fn reserve_firmware_in_nezha() {
+        let mut buf = nezha();
+        apply_ok(&mut buf, &PARAMS);
+        let parsed = parse(&buf);
+        assert_eq!(
+            parsed.reserved,
+            [(
+                "mmode_resv0@40000000".into(),
+                vec![(0x4000_0000, 0x4020_0000)],
+                true
+            )]
+        );
+    }
+
+    #[test]
+    fn reserve_is_idempotent() {
+        let mut buf = nezha();
+        apply_ok(&mut buf, &PARAMS);
+        apply_ok(&mut buf, &PARAMS);
+        assert_eq!(parse(&buf).reserved.len(), 1);
+    }
+
+    #[test]
+    fn existing_reserved_memory() {
+        let mut buf = dtc(br#"/dts-v1/;
+            / {
+                #address-cells = <1>;
+                #size-cells = <1>;
+                reserved-memory {
+                    #address-cells = <1>;
+                    #size-cells = <1>;
+                    ranges;
+                    dsp@42000000 {
+                        reg = <0x42000000 0x100000>;
+                    };
+                };
+            };
+        "#);
+        apply_ok(&mut buf, &PARAMS);
+        let parsed = parse(&buf);
+        assert_eq!(parsed.reserved.len(), 2);
+        assert_eq!(parsed.reserved[0].1, [(0x4200_0000, 0x4210_0000)]);
+        assert!(!parsed.reserved[0].2);
+        assert_eq!(parsed.reserved[1].0, "mmode_resv0@40000000");
+        assert_eq!(parsed.reserved[1].1, [(0x4000_0000, 0x4020_0000)]);
+        assert!(parsed.reserved[1].2);
+    }
+
+    #[test]
+    fn chosen_keeps_stdout_and_overrides_bootargs() {
+        let mut buf = nezha();
+        let bootargs = c"console=ttyS0 root=/dev/mmcblk0p2";
+        let params = Params {
+            bootargs: Some(bootargs),
+            initrd: Some(0x4100_0000..0x4180_0000),
+            ..PARAMS
+        };
+        apply_ok(&mut buf, &params);
+        let chosen = parse(&buf).chosen;
+        let get = |name: &str| {
+            chosen
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.as_slice())
+        };
+        assert_eq!(get("stdout-path"), Some(&b"serial0:115200n8\0"[..]));
+        assert_eq!(get("bootargs"), Some(bootargs.to_bytes_with_nul()));
+        assert_eq!(
+            get("linux,initrd-start"),
+            Some(&0x4100_0000u64.to_be_bytes()[..])
+        );
+        assert_eq!(
+            get("linux,initrd-end"),
+            Some(&0x4180_0000u64.to_be_bytes()[..])
+        );
+    }
+
+    #[test]
+    fn chosen_added_when_missing() {
+        let mut buf = dtc(b"/dts-v1/; / { #address-cells = <2>; #size-cells = <2>; };");
+        apply_ok(&mut buf, &PARAMS);
+        let parsed = parse(&buf);
+        assert_eq!(
+            parsed.chosen,
+            [("stdout-path".into(), b"serial0:115200n8\0".to_vec())]
+        );
+        assert_eq!(parsed.reserved[0].1, [(0x4000_0000, 0x4020_0000)]);
+    }
+
+    #[test]
+    fn truncated_blob() {
+        let buf = nezha();
+        let total = u32::from_be_bytes(buf[4..8].try_into().unwrap()) as usize;
+        for len in [8, 0x40, total / 2, total - 1] {
+            let mut truncated = buf[..len].to_vec();
+            assert!(Fdt::new(&mut truncated).is_err(), "accepted {len} bytes");
+        }
+    }
+
+    #[test]
+    fn corrupt_header() {
+        let buf = nezha();
+        // (字段偏移, 值)：结构块、字符串块和内存保留表越出设备树
+        for (off, val) in [
+            (0x08, u32::MAX),
+            (0x0c, u32::MAX),
+            (0x10, u32::MAX),
+            (0x10, 0),
+            (0x20, u32::MAX),
+            (0x24, u32::MAX),
+        ] {
+            let mut corrupt = buf.clone();
+            corrupt[off..off + 4].copy_from_slice(&val.to_be_bytes());
+            assert_eq!(
+                Fdt::new(&mut corrupt).err(),
+                Some(Error::BadLayout),
+                "field {off:#x}"
+            );
+        }
+    }
+
+    #[test]
+    fn unsupported_cells() {
+        let mut buf = dtc(b"/dts-v1/; / { #address-cells = <3>; #size-cells = <2>; };");
+        let mut fdt = Fdt::new(&mut buf).unwrap();
+        assert_eq!(
+            reserve(&mut fdt, "mmode_resv0", PARAMS.firmware),
+            Err(Error::BadCells)
+        );
+        assert!(fdt.find_node("/reserved-memory").is_none());
+
+        let mut reg = [0u8; 16];
+        assert_eq!(encode_cells(&mut reg, 1, 0), Err(Error::BadCells));
+        assert_eq!(encode_cells(&mut reg, 1, 3), Err(Error::BadCells));
+        assert_eq!(encode_cells(&mut reg, 0x1234, 1), Ok(4));
+        assert_eq!(encode_cells(&mut reg, 0x1234, 2), Ok(8));
+        assert_eq!(reg[..8], 0x1234u64.to_be_bytes());
+    }
+}
@@ -1,7 +1,11 @@
-﻿pub const META: u32 = 2 << 20; // 2 MiB
+﻿use crate::policy::Policy;
+
+pub const META: u32 = 2 << 20; // 2 MiB
+pub const BOOTARGS: u32 = 3 << 20; // 3 MiB
 pub const SEE: u32 = 4 << 20; // 4 MiB
 pub const DTB: u32 = 6 << 20; // 6 MiB
 pub const KERNEL: u32 = 8 << 20; // 8 MiB
+pub const INITRD: u32 = 64 << 20; // 64 MiB
 
 #[derive(Debug)]
 #[repr(C)]
@@ -9,6 +13,10 @@ pub struct Meta {
     see: MetaEntry,
     kernel: MetaEntry,
     dtb: MetaEntry,
+    bootargs: MetaEntry,
+    initrd: MetaEntry,
+    /// SEE 的启动策略，见 [`Policy`]。
+    policy: u32,
 }
 
 #[derive(Debug)]
@@ -46,11 +54,16 @@ impl Meta {
         see: MetaEntry::DEFAULT,
         kernel: MetaEntry::DEFAULT,
         dtb: MetaEntry::DEFAULT,
+        bootargs: MetaEntry::DEFAULT,
+        initrd: MetaEntry::DEFAULT,
+        policy: !0,
     };
 
     read_payload!(see);
     read_payload!(kernel);
     read_payload!(dtb);
+    read_payload!(bootargs);
+    read_payload!(initrd);
 
     #[inline]
     pub fn set_see(&mut self, base: u32, size: u32) {
@@ -66,4 +79,25 @@ impl Meta {
     pub fn set_dtb(&mut self, base: u32, size: u32) {
         self.dtb = MetaEntry { offset: base, size };
     }
+
+    #[inline]
+    pub fn set_bootargs(&mut self, base: u32, size: u32) {
+        self.bootargs = MetaEntry { offset: base, size };
+    }
+
+    #[inline]
+    pub fn set_initrd(&mut self, base: u32, size: u32) {
+        self.initrd = MetaEntry { offset: base, size };
+    }
+
+    /// 启动策略，擦除的 flash 和旧的元数据上没有这一项。
+    #[inline]
+    pub fn policy(&self) -> Option<Policy> {
+        u8::try_from(self.policy).ok().and_then(Policy::from_u8)
+    }
+
+    #[inline]
+    pub fn set_policy(&mut self, policy: Policy) {
+        self.policy = policy as _;
+    }
 }
@@ -0,0 +1,187 @@
+//! 硬件线程状态机。
+//!
+//! 状态机本身不访问任何硬件，只描述 SBI HSM 扩展规定的状态转移。
+//! 状态的值与 SBI 规范一致，可以直接作为 `hart_get_status` 的返回值。
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub const STARTED: usize = 0;
+pub const STOPPED: usize = 1;
+pub const START_PENDING: usize = 2;
+pub const STOP_PENDING: usize = 3;
+pub const SUSPENDED: usize = 4;
+pub const SUSPEND_PENDING: usize = 5;
+pub const RESUME_PENDING: usize = 6;
+
+/// 状态转移失败的原因。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// 启动一个没有停止的硬件线程，对应 `SBI_ERR_ALREADY_AVAILABLE`。
+    AlreadyAvailable,
+    /// 硬件线程已经在执行特权软件，对应 `SBI_ERR_ALREADY_STARTED`。
+    AlreadyStarted,
+    /// 当前状态不允许这个转移，附带当前状态。
+    InvalidState(usize),
+}
+
+/// 硬件线程状态机。
+pub struct HartState(AtomicUsize);
+
+impl HartState {
+    #[inline]
+    pub const fn new(state: usize) -> Self {
+        Self(AtomicUsize::new(state))
+    }
+
+    /// 当前状态。
+    #[inline]
+    pub fn status(&self) -> usize {
+        self.0.load(Ordering::Acquire)
+    }
+
+    /// 请求启动：`STOPPED` -> `START_PENDING`。
+    #[inline]
+    pub fn start(&self) -> Result<(), Error> {
+        self.transit(|s| s == STOPPED, START_PENDING)
+            .map_err(|_| Error::AlreadyAvailable)
+    }
+
+    /// 特权软件已开始执行：`START_PENDING` 或 `RESUME_PENDING` -> `STARTED`。
+    #[inline]
+    pub fn started(&self) -> Result<(), Error> {
+        self.transit(|s| s == START_PENDING || s == RESUME_PENDING, STARTED)
+            .map_err(|s| match s {
+                STARTED => Error::AlreadyStarted,
+                s => Error::InvalidState(s),
+            })
+    }
+
+    /// 请求停止：`STARTED` -> `STOP_PENDING`。
+    #[inline]
+    pub fn stop(&self) -> Result<(), Error> {
+        self.transit(|s| s == STARTED, STOP_PENDING)
+            .map_err(Error::InvalidState)
+    }
+
+    /// 已经停止：`STOP_PENDING` -> `STOPPED`。
+    #[inline]
+    pub fn stopped(&self) -> Result<(), Error> {
+        self.transit(|s| s == STOP_PENDING, STOPPED)
+            .map_err(Error::InvalidState)
+    }
+
+    /// 进入挂起：`STARTED` -> `SUSPENDED`。
+    #[inline]
+    pub fn suspend(&self) -> Result<(), Error> {
+        self.transit(|s| s == STARTED, SUSPENDED)
+            .map_err(Error::InvalidState)
+    }
+
+    /// 从挂起中唤醒。
+    ///
+    /// 保持上下文的挂起直接回到 `STARTED`，不保持上下文的挂起要先经过 `RESUME_PENDING`。
+    #[inline]
+    pub fn resume(&self, retentive: bool) -> Result<(), Error> {
+        let next = if retentive { STARTED } else { RESUME_PENDING };
+        self.transit(|s| s == SUSPENDED, next)
+            .map_err(Error::InvalidState)
+    }
+
+    /// 当前状态满足 `from` 时转移到 `new`，否则返回当前状态。
+    #[inline]
+    fn transit(&self, from: impl Fn(usize) -> bool, new: usize) -> Result<(), usize> {
+        self.0
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |s| {
+                from(s).then_some(new)
+            })
+            .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boot_and_stop() {
+        let hart = HartState::new(START_PENDING);
+        assert_eq!(hart.started(), Ok(()));
+        assert_eq!(hart.status(), STARTED);
+        assert_eq!(hart.stop(), Ok(()));
+        assert_eq!(hart.status(), STOP_PENDING);
+        assert_eq!(hart.stopped(), Ok(()));
+        assert_eq!(hart.status(), STOPPED);
+        assert_eq!(hart.start(), Ok(()));
+        assert_eq!(hart.status(), START_PENDING);
+    }
+
+    #[test]
+    fn start_not_stopped() {
+        for state in [
+            STARTED,
+            START_PENDING,
+            STOP_PENDING,
+            SUSPENDED,
+            SUSPEND_PENDING,
+            RESUME_PENDING,
+        ] {
+            let hart = HartState::new(state);
+            assert_eq!(hart.start(), Err(Error::AlreadyAvailable));
+            assert_eq!(hart.status(), state);
+        }
+    }
+
+    #[test]
+    fn started_twice() {
+        let hart = HartState::new(START_PENDING);
+        assert_eq!(hart.started(), Ok(()));
+        assert_eq!(hart.started(), Err(Error::AlreadyStarted));
+        assert_eq!(hart.status(), STARTED);
+    }
+
+    #[test]
+    fn started_from_stopped() {
+        let hart = HartState::new(STOPPED);
+        assert_eq!(hart.started(), Err(Error::InvalidState(STOPPED)));
+        assert_eq!(hart.status(), STOPPED);
+    }
+
+    #[test]
+    fn stop_not_started() {
+        for state in [STOPPED, START_PENDING, STOP_PENDING, SUSPENDED] {
+            let hart = HartState::new(state);
+            assert_eq!(hart.stop(), Err(Error::InvalidState(state)));
+            assert_eq!(hart.status(), state);
+        }
+        let hart = HartState::new(STARTED);
+        assert_eq!(hart.stopped(), Err(Error::InvalidState(STARTED)));
+    }
+
+    #[test]
+    fn retentive_suspend() {
+        let hart = HartState::new(STARTED);
+        assert_eq!(hart.suspend(), Ok(()));
+        assert_eq!(hart.status(), SUSPENDED);
+        assert_eq!(hart.suspend(), Err(Error::InvalidState(SUSPENDED)));
+        assert_eq!(hart.resume(true), Ok(()));
+        assert_eq!(hart.status(), STARTED);
+    }
+
+    #[test]
+    fn non_retentive_suspend() {
+        let hart = HartState::new(STARTED);
+        assert_eq!(hart.suspend(), Ok(()));
+        assert_eq!(hart.resume(false), Ok(()));
+        assert_eq!(hart.status(), RESUME_PENDING);
+        assert_eq!(hart.started(), Ok(()));
+        assert_eq!(hart.status(), STARTED);
+    }
+
+    #[test]
+    fn resume_not_suspended() {
+        let hart = HartState::new(STARTED);
+        assert_eq!(hart.resume(true), Err(Error::InvalidState(STARTED)));
+        assert_eq!(hart.resume(false), Err(Error::InvalidState(STARTED)));
+        assert_eq!(hart.status(), STARTED);
+    }
+}
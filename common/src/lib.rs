@@ -1,8 +1,16 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 mod arrow;
+pub mod csr;
+pub mod elf;
+pub mod fdt;
+pub mod fixup;
 pub mod flash;
+pub mod hsm;
 pub mod memory;
+pub mod pmp;
+pub mod policy;
+pub mod timer;
 
 pub extern crate dtb_walker;
 pub use arrow::Arrow;
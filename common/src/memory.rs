@@ -1,21 +1,46 @@
-﻿pub const SRAM: usize = 0x0002_0000;
+﻿use crate::policy::Policy;
+use core::ops::Range;
+
+pub const SRAM: usize = 0x0002_0000;
 pub const DRAM: usize = 0x4000_0000;
 pub const KERNEL: usize = 0x4020_0000;
 pub const META: usize = 0x0002_0068;
+/// 启动参数放在内核之前的一页。
+pub const BOOTARGS: usize = KERNEL - BOOTARGS_MAX;
+pub const BOOTARGS_MAX: usize = 4096;
+/// 崩溃记录放在启动参数之前的一页，热复位后仍然保留。
+pub const CRASH: usize = BOOTARGS - CRASH_SIZE;
+pub const CRASH_SIZE: usize = 4096;
+
+/// 设备树占用的空间，设备树可以在其中原地扩展。
+pub const DTB_WINDOW: usize = 2 << 20;
 
 #[inline]
 pub fn dtb_offset(mem_size: usize) -> u32 {
-    const PAGE: u32 = 2 << 20;
+    const PAGE: u32 = DTB_WINDOW as _;
     ((mem_size as u32).min(1 << 30) - PAGE) & !(PAGE - 1)
 }
 
+/// 初始内存盘紧挨着放在设备树之前，起点按 2 MiB 对齐。
+#[inline]
+pub const fn initrd_offset(dtb_offset: u32, len: u32) -> u32 {
+    const PAGE: u32 = DTB_WINDOW as _;
+    dtb_offset.saturating_sub(len) & !(PAGE - 1)
+}
+
 #[repr(C)]
 pub struct Meta {
     pub from_flash: bool,
-    _zero: [u8; 3],
+    /// SEE 的启动策略，见 [`Policy`](crate::policy::Policy)。
+    policy: u8,
+    /// SPL 探测到的主存大小，单位 MiB。
+    dram_size: u16,
     pub see: u32,
     pub kernel: u32,
     pub dtb: u32,
+    pub bootargs: u32,
+    /// 初始内存盘的大小，位置由设备树的位置决定。
+    pub initrd: u32,
 }
 
 const NONE: u32 = !0;
@@ -37,10 +62,13 @@ impl crate::AsBinary for Meta {}
 impl Meta {
     pub const DEFAULT: Self = Self {
         from_flash: false,
-        _zero: [!0; 3],
+        policy: !0,
+        dram_size: !0,
         see: NONE,
         kernel: NONE,
         dtb: NONE,
+        bootargs: NONE,
+        initrd: NONE,
     };
 
     #[inline]
@@ -61,6 +89,47 @@ impl Meta {
     read_payload!(see);
     read_payload!(kernel);
     read_payload!(dtb);
+    read_payload!(bootargs);
+
+    /// 初始内存盘的位置。
+    #[inline]
+    pub const fn initrd(&self) -> Option<Range<usize>> {
+        match (self.dtb, self.initrd) {
+            (NONE, _) | (_, NONE) => None,
+            (dtb, len) => {
+                let start = DRAM + initrd_offset(dtb, len) as usize;
+                Some(start..start + len as usize)
+            }
+        }
+    }
+
+    /// SPL 探测到的主存大小。
+    #[inline]
+    pub const fn dram_size(&self) -> Option<usize> {
+        match self.dram_size {
+            0xffff => None,
+            mib => Some((mib as usize) << 20),
+        }
+    }
+
+    /// 启动策略，没有指定或不认识时使用默认策略。
+    #[inline]
+    pub const fn policy(&self) -> Policy {
+        match Policy::from_u8(self.policy) {
+            Some(policy) => policy,
+            None => Policy::DEFAULT,
+        }
+    }
+
+    #[inline]
+    pub fn set_policy(&mut self, policy: Policy) {
+        self.policy = policy as _;
+    }
+
+    #[inline]
+    pub fn set_dram_size(&mut self, size: usize) {
+        self.dram_size = (size >> 20) as _;
+    }
 
     #[inline]
     pub fn set_see(&mut self, val: u32) {
@@ -76,6 +145,16 @@ impl Meta {
     pub fn set_dtb(&mut self, val: u32) {
         self.dtb = val;
     }
+
+    #[inline]
+    pub fn set_bootargs(&mut self, val: u32) {
+        self.bootargs = val;
+    }
+
+    #[inline]
+    pub fn set_initrd(&mut self, len: u32) {
+        self.initrd = len;
+    }
 }
 
 #[allow(clippy::not_unsafe_ptr_arg_deref)]
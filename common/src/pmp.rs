@@ -0,0 +1,490 @@
+//! PMP 规划。
+//!
+//! 从设备树收集区域，展平成互不重叠的段，再为每段选择 NAPOT 或 TOR 编码，
+//! 使条目数最少。条目不够时合并同权限的相邻段，只放宽段之间未描述的空隙。
+//!
+//! 规划不访问硬件，结果由 SEE 写入 PMP。
+
+use core::ops::Range;
+
+/// 可读。
+pub const R: u8 = 1 << 0;
+/// 可写。
+pub const W: u8 = 1 << 1;
+/// 可执行。
+pub const X: u8 = 1 << 2;
+
+pub const OFF: u8 = 0b00 << 3;
+pub const TOR: u8 = 0b01 << 3;
+pub const NAPOT: u8 = 0b11 << 3;
+
+/// PMP 条目数。
+pub const ENTRIES: usize = 16;
+const MAX_REGIONS: usize = 64;
+const MAX_SEGMENTS: usize = MAX_REGIONS * 2;
+
+/// 区域和特权软件对它的权限。
+#[derive(Clone, Debug)]
+pub struct Region {
+    pub range: Range<usize>,
+    pub perm: u8,
+}
+
+impl Region {
+    const EMPTY: Self = Self {
+        range: 0..0,
+        perm: 0,
+    };
+}
+
+/// 按加入顺序排列的区域，重叠时后加入的区域生效。
+///
+/// 规划用的段也放在这里，整个结构体较大，应该放在静态变量中。
+pub struct Regions {
+    items: [Region; MAX_REGIONS],
+    len: usize,
+    /// 展平的段，按地址排列、互不重叠。
+    segments: [Region; MAX_SEGMENTS],
+    n_segments: usize,
+}
+
+impl Regions {
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            items: [Region::EMPTY; MAX_REGIONS],
+            len: 0,
+            segments: [Region::EMPTY; MAX_SEGMENTS],
+            n_segments: 0,
+        }
+    }
+
+    /// 清空所有区域。
+    #[inline]
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.n_segments = 0;
+    }
+
+    /// 加入区域，空间不足返回 `false`。
+    pub fn push(&mut self, range: Range<usize>, perm: u8) -> bool {
+        if range.is_empty() {
+            return true;
+        }
+        if self.len == MAX_REGIONS {
+            return false;
+        }
+        self.items[self.len] = Region { range, perm };
+        self.len += 1;
+        true
+    }
+
+    /// 从位于 `addr` 的设备树收集区域。
+    ///
+    /// 根节点和 `soc` 下的设备可读写，主存可读写执行，`/reserved-memory` 可读写不可执行，
+    /// 设备树本身只读。
+    pub fn collect_dtb(&mut self, addr: usize) -> bool {
+        use dtb_walker::{Dtb, DtbObj, HeaderError::*, Property, WalkOperation::*};
+
+        let dtb = unsafe {
+            match Dtb::from_raw_parts_filtered(addr as _, |e| matches!(e, LastCompVersion(16))) {
+                Ok(dtb) => dtb,
+                Err(_) => return false,
+            }
+        };
+        let size = dtb.total_size();
+        let mut ok = true;
+        let mut perm = 0;
+        dtb.walk(|path, obj| match obj {
+            DtbObj::SubNode { name } => {
+                if path.is_root() {
+                    perm = if name.starts_with("memory") {
+                        R | W | X
+                    } else {
+                        R | W
+                    };
+                    if name.starts_with("cpus") {
+                        StepOver
+                    } else {
+                        StepInto
+                    }
+                } else if path.name().starts_with("soc")
+                    || path.name().starts_with("reserved-memory")
+                {
+                    perm = R | W;
+                    StepInto
+                } else {
+                    StepOver
+                }
+            }
+            DtbObj::Property(Property::Reg(reg)) => {
+                for range in reg {
+                    ok &= self.push(range, perm);
+                }
+                StepOver
+            }
+            DtbObj::Property(_) => StepOver,
+        });
+        ok && self.push(addr..addr + size, R)
+    }
+
+    /// 规划 PMP 条目，条目不够时返回 `None`。
+    pub fn plan(&mut self) -> Option<Plan> {
+        self.flatten();
+        loop {
+            if let Some(plan) = self.encode() {
+                return Some(plan);
+            }
+            if !self.merge_closest() {
+                return None;
+            }
+        }
+    }
+
+    /// 展平成按地址排列、互不重叠的段，相邻的同权限段合并。
+    fn flatten(&mut self) {
+        let mut bounds = [0usize; MAX_SEGMENTS + 1];
+        let mut n = 0;
+        for region in &self.items[..self.len] {
+            for b in [region.range.start, region.range.end] {
+                if !bounds[..n].contains(&b) {
+                    bounds[n] = b;
+                    n += 1;
+                }
+            }
+        }
+        bounds[..n].sort_unstable();
+
+        self.n_segments = 0;
+        for i in 1..n {
+            let (start, end) = (bounds[i - 1], bounds[i]);
+            let Some(perm) = self.items[..self.len]
+                .iter()
+                .rev()
+                .find(|r| r.range.start <= start && end <= r.range.end)
+                .map(|r| r.perm)
+            else {
+                continue;
+            };
+            match self.segments[..self.n_segments].last_mut() {
+                Some(last) if last.range.end == start && last.perm == perm => last.range.end = end,
+                _ => {
+                    self.segments[self.n_segments] = Region {
+                        range: start..end,
+                        perm,
+                    };
+                    self.n_segments += 1;
+                }
+            }
+        }
+    }
+
+    /// 合并间隔最小的一对同权限段，它们之间必须是未描述的空隙。
+    fn merge_closest(&mut self) -> bool {
+        let segs = &self.segments[..self.n_segments];
+        let Some(i) = (1..segs.len())
+            .filter(|&i| {
+                segs[i].perm != 0
+                    && segs[i].perm == segs[i - 1].perm
+                    && segs[i - 1].range.end < segs[i].range.start
+            })
+            .min_by_key(|&i| segs[i].range.start - segs[i - 1].range.end)
+        else {
+            return false;
+        };
+        self.segments[i - 1].range.end = self.segments[i].range.end;
+        for j in i..self.n_segments - 1 {
+            self.segments[j] = self.segments[j + 1].clone();
+        }
+        self.n_segments -= 1;
+        true
+    }
+
+    /// 用最少的条目编码所有可访问的段，不可访问的段不需要条目。
+    ///
+    /// TOR 条目以前一个条目的地址为起点，所以紧接在 TOR 条目之后的段只要一个条目，
+    /// 否则要多用一个关闭的条目设置起点；NAPOT 条目只能描述对齐的 2 的幂大小的段。
+    fn encode(&self) -> Option<Plan> {
+        const INF: u8 = u8::MAX;
+        const NAPOT_: usize = 0;
+        const TOR_: usize = 1;
+
+        let segs = &self.segments[..self.n_segments];
+        // cost[i][k]：编码到第 i 段的最少条目数，k 表示第 i 段的编码方式
+        let mut cost = [[INF; 2]; MAX_SEGMENTS + 1];
+        let mut from = [[NAPOT_; 2]; MAX_SEGMENTS + 1];
+        // 第一个条目的 TOR 起点是 0
+        cost[0] = [INF, 0];
+        let mut tor_end = 0;
+        let mut last = 0;
+        for (i, seg) in segs.iter().enumerate() {
+            let i = i + 1;
+            if seg.perm == 0 {
+                cost[i] = cost[last];
+                from[i] = [NAPOT_, TOR_];
+                continue;
+            }
+            let prev = cost[last];
+            let (best, k) = if prev[NAPOT_] <= prev[TOR_] {
+                (prev[NAPOT_], NAPOT_)
+            } else {
+                (prev[TOR_], TOR_)
+            };
+            if napot(&seg.range) {
+                cost[i][NAPOT_] = best.saturating_add(1);
+                from[i][NAPOT_] = k;
+            }
+            let (mut tor, mut k) = (best.saturating_add(2), k);
+            if prev[TOR_] != INF && tor_end == seg.range.start && prev[TOR_] + 1 < tor {
+                (tor, k) = (prev[TOR_] + 1, TOR_);
+            }
+            cost[i][TOR_] = tor;
+            from[i][TOR_] = k;
+            tor_end = seg.range.end;
+            last = i;
+        }
+        let total = cost[last];
+        let mut k = if total[NAPOT_] <= total[TOR_] {
+            NAPOT_
+        } else {
+            TOR_
+        };
+        if total[k] as usize > ENTRIES {
+            return None;
+        }
+        // 回溯每段的编码
+        let mut choice = [NAPOT_; MAX_SEGMENTS];
+        for i in (0..segs.len()).rev() {
+            choice[i] = k;
+            k = from[i + 1][k];
+        }
+        let mut plan = Plan::new();
+        let mut tor_base = Some(0);
+        for (seg, k) in segs.iter().zip(choice) {
+            if seg.perm == 0 {
+                continue;
+            }
+            let Range { start, end } = seg.range;
+            if k == TOR_ {
+                if tor_base != Some(start) {
+                    plan.push(OFF, start >> 2);
+                }
+                plan.push(TOR | seg.perm, end >> 2);
+                tor_base = Some(end);
+            } else {
+                plan.push(NAPOT | seg.perm, (start | ((end - start) / 2 - 1)) >> 2);
+                tor_base = None;
+            }
+        }
+        Some(plan)
+    }
+}
+
+impl Default for Regions {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 能否用 NAPOT 编码。
+#[inline]
+fn napot(range: &Range<usize>) -> bool {
+    let size = range.end - range.start;
+    size >= 8 && size.is_power_of_two() && range.start & (size - 1) == 0
+}
+
+/// PMP 条目：`(pmpcfg, pmpaddr)`。
+pub struct Plan {
+    entries: [(u8, usize); ENTRIES],
+    len: usize,
+}
+
+impl Plan {
+    #[inline]
+    const fn new() -> Self {
+        Self {
+            entries: [(OFF, 0); ENTRIES],
+            len: 0,
+        }
+    }
+
+    #[inline]
+    fn push(&mut self, cfg: u8, addr: usize) {
+        self.entries[self.len] = (cfg, addr);
+        self.len += 1;
+    }
+
+    /// 所有条目，未使用的条目关闭。
+    #[inline]
+    pub fn entries(&self) -> &[(u8, usize); ENTRIES] {
+        &self.entries
+    }
+
+    /// 使用的条目数。
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 按 PMP 的匹配规则求 `addr` 的权限，没有条目匹配时不可访问。
+    fn perm(plan: &Plan, addr: usize) -> u8 {
+        let mut base = 0;
+        for &(cfg, pmpaddr) in plan.entries() {
+            let range = match cfg & NAPOT {
+                TOR => base..pmpaddr << 2,
+                NAPOT => {
+                    let t = pmpaddr.trailing_ones();
+                    let start = (pmpaddr & !((1 << t) - 1)) << 2;
+                    start..start + (8 << t)
+                }
+                _ => 0..0,
+            };
+            if range.contains(&addr) {
+                return cfg & (R | W | X);
+            }
+            base = pmpaddr << 2;
+        }
+        0
+    }
+
+    fn plan(regions: &[(Range<usize>, u8)]) -> Option<Plan> {
+        let mut r = Regions::new();
+        for (range, perm) in regions {
+            assert!(r.push(range.clone(), *perm));
+        }
+        r.plan()
+    }
+
+    #[test]
+    fn aligned_region_uses_napot() {
+        let plan = plan(&[(0x4000_0000..0x8000_0000, R | W | X)]).unwrap();
+        assert_eq!(plan.len(), 1);
+        assert_eq!(plan.entries()[0].0, NAPOT | R | W | X);
+        assert_eq!(perm(&plan, 0x4000_0000), R | W | X);
+        assert_eq!(perm(&plan, 0x7fff_ffff), R | W | X);
+        assert_eq!(perm(&plan, 0x3fff_ffff), 0);
+        assert_eq!(perm(&plan, 0x8000_0000), 0);
+    }
+
+    #[test]
+    fn unaligned_region_uses_tor() {
+        let plan = plan(&[(0x4000_0000..0x4060_0000, R | W)]).unwrap();
+        assert_eq!(plan.len(), 2);
+        assert_eq!(plan.entries()[0], (OFF, 0x4000_0000 >> 2));
+        assert_eq!(plan.entries()[1], (TOR | R | W, 0x4060_0000 >> 2));
+        assert_eq!(perm(&plan, 0x405f_ffff), R | W);
+        assert_eq!(perm(&plan, 0x4060_0000), 0);
+    }
+
+    #[test]
+    fn adjacent_tor_shares_base() {
+        let plan = plan(&[
+            (0..0x4000_0000, R | W),
+            (0x4000_0000..0x4060_0000, R | W | X),
+        ])
+        .unwrap();
+        // 从 0 开始的 TOR 不需要起点，第二段以第一段的终点为起点
+        assert_eq!(plan.len(), 2);
+        assert_eq!(perm(&plan, 0), R | W);
+        assert_eq!(perm(&plan, 0x4000_0000), R | W | X);
+        assert_eq!(perm(&plan, 0x4060_0000), 0);
+    }
+
+    #[test]
+    fn reserved_memory_overrides_main_memory() {
+        let plan = plan(&[
+            (0x4000_0000..0x8000_0000, R | W | X),
+            // /reserved-memory
+            (0x4200_0000..0x4210_0000, R | W),
+            // SBI
+            (0x4000_0000..0x4020_0000, 0),
+        ])
+        .unwrap();
+        assert_eq!(perm(&plan, 0x4000_0000), 0);
+        assert_eq!(perm(&plan, 0x401f_ffff), 0);
+        assert_eq!(perm(&plan, 0x4020_0000), R | W | X);
+        assert_eq!(perm(&plan, 0x4200_0000), R | W);
+        assert_eq!(perm(&plan, 0x420f_ffff), R | W);
+        assert_eq!(perm(&plan, 0x4210_0000), R | W | X);
+        assert_eq!(perm(&plan, 0x7fff_ffff), R | W | X);
+    }
+
+    #[test]
+    fn later_region_wins() {
+        // 不可访问的区域在前，被后加入的主存覆盖
+        let plan = plan(&[
+            (0x4000_0000..0x4020_0000, 0),
+            (0x4000_0000..0x8000_0000, R | W | X),
+        ])
+        .unwrap();
+        assert_eq!(plan.len(), 1);
+        assert_eq!(perm(&plan, 0x4000_0000), R | W | X);
+    }
+
+    #[test]
+    fn dtb_is_read_only() {
+        let dtb = 0x4fff_0000..0x4fff_8a3c;
+        let plan = plan(&[
+            (0x4000_0000..0x8000_0000, R | W | X),
+            (0x4000_0000..0x4020_0000, 0),
+            (dtb.clone(), R),
+        ])
+        .unwrap();
+        assert_eq!(perm(&plan, dtb.start - 1), R | W | X);
+        assert_eq!(perm(&plan, dtb.start), R);
+        assert_eq!(perm(&plan, dtb.end - 1), R);
+        assert_eq!(perm(&plan, dtb.end), R | W | X);
+    }
+
+    #[test]
+    fn merge_gaps_when_out_of_entries() {
+        // 20 个不对齐的同权限区域，合并时只放宽它们之间的空隙
+        let regions: [_; 20] = core::array::from_fn(|i| {
+            let start = 0x1000_0000 + i * 0x10_0000;
+            (start..start + 0x1000 + i * 0x100, R | W)
+        });
+        let plan = plan(&regions).unwrap();
+        assert!(plan.len() <= ENTRIES);
+        for (range, _) in &regions {
+            assert_eq!(perm(&plan, range.start), R | W);
+            assert_eq!(perm(&plan, range.end - 1), R | W);
+        }
+        assert_eq!(perm(&plan, 0x1000_0000 - 1), 0);
+        assert_eq!(perm(&plan, regions[19].0.end), 0);
+    }
+
+    #[test]
+    fn out_of_entries() {
+        // 权限交替的段不能合并
+        let regions: [_; 20] = core::array::from_fn(|i| {
+            let start = 0x1000_0000 + i * 0x10_0000;
+            let perm = if i % 2 == 0 { R | W } else { R };
+            (start..start + 0x1000, perm)
+        });
+        assert!(plan(&regions).is_none());
+        assert!(plan(&regions[..16]).is_some());
+    }
+
+    #[test]
+    fn too_many_regions() {
+        let mut r = Regions::new();
+        for i in 0..MAX_REGIONS {
+            assert!(r.push(i * 0x1000..i * 0x1000 + 8, R));
+        }
+        assert!(!r.push(0..8, R));
+        assert!(r.push(0..0, R));
+        r.clear();
+        assert!(r.push(0..8, R));
+    }
+}
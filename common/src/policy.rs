@@ -0,0 +1,39 @@
+//! SEE 启动时设置平头哥扩展 CSR 的策略。
+//!
+//! 策略编号保存在 flash 元数据中，由 SPL 传给 SEE。
+
+/// 启动策略。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum Policy {
+    /// 适配带平头哥补丁的 Linux：开缓存和分支预测，使能 MAEE 页属性、扩展指令和非对齐访问。
+    Linux = 0,
+    /// 适配只认识标准页表项的内核：开缓存、分支预测和扩展指令，不使能 MAEE，非对齐访问由 SEE 模拟。
+    Standard = 1,
+    /// 保持 BROM 的设置。
+    Keep = 2,
+}
+
+impl Policy {
+    /// 元数据没有指定策略时使用的策略，需要时再选择 `linux`。
+    pub const DEFAULT: Self = Self::Keep;
+
+    #[inline]
+    pub const fn from_u8(val: u8) -> Option<Self> {
+        match val {
+            0 => Some(Self::Linux),
+            1 => Some(Self::Standard),
+            2 => Some(Self::Keep),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Linux => "linux",
+            Self::Standard => "standard",
+            Self::Keep => "keep",
+        }
+    }
+}
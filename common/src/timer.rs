@@ -0,0 +1,195 @@
+//! 机器态定时器的语义。
+//!
+//! C906 的 CLINT 只能按 32 位访问 `mtimecmp`，两次写入之间不能让比较值短暂小于目标值，
+//! 否则会提前触发中断。硬件访问抽象为 [`Clint`]，定时器的行为可以在主机上测试。
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// 定时器用到的硬件操作。
+pub trait Clint {
+    /// 当前时间。
+    fn time(&self) -> u64;
+    /// 写 `hartid` 的 `mtimecmp` 的低 32 位或高 32 位。
+    fn write_mtimecmp(&self, hartid: usize, high: bool, val: u32);
+    /// 挂起或清除 `mip.STIP`。
+    fn set_stip(&self, pending: bool);
+}
+
+/// 已设置而尚未触发的定时器，每个硬件线程一位。
+///
+/// 与 `AtomicUsize` 布局相同，定时器中断代理可以直接在汇编中清除对应的位。
+#[repr(transparent)]
+pub struct Timer(AtomicUsize);
+
+impl Timer {
+    #[inline]
+    pub const fn new() -> Self {
+        Self(AtomicUsize::new(0))
+    }
+
+    /// 为 `hartid` 设置下一次定时器中断，并清除已挂起的 stip。
+    ///
+    /// 设置一个已经过去的时间会立即挂起 stip。
+    pub fn set(&self, clint: &impl Clint, hartid: usize, stime_value: u64) {
+        clint.set_stip(false);
+        if stime_value <= clint.time() {
+            self.disarm(hartid);
+            write_mtimecmp(clint, hartid, u64::MAX);
+            clint.set_stip(true);
+        } else {
+            self.0.fetch_or(1 << hartid, Ordering::AcqRel);
+            write_mtimecmp(clint, hartid, stime_value);
+        }
+    }
+
+    /// 定时器中断代理的行为：清除 `mtimecmp` 和已设置标记，挂起 stip。
+    pub fn fire(&self, clint: &impl Clint, hartid: usize) {
+        write_mtimecmp(clint, hartid, u64::MAX);
+        self.disarm(hartid);
+        clint.set_stip(true);
+    }
+
+    /// 定时器是否已设置而尚未触发。
+    #[inline]
+    pub fn armed(&self, hartid: usize) -> bool {
+        self.0.load(Ordering::Acquire) & (1 << hartid) != 0
+    }
+
+    #[inline]
+    fn disarm(&self, hartid: usize) {
+        self.0.fetch_and(!(1 << hartid), Ordering::AcqRel);
+    }
+}
+
+impl Default for Timer {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 无毛刺地写 `mtimecmp`：先把高位写成最大值，再写低位，最后写高位。
+pub fn write_mtimecmp(clint: &impl Clint, hartid: usize, val: u64) {
+    clint.write_mtimecmp(hartid, true, u32::MAX);
+    clint.write_mtimecmp(hartid, false, val as u32);
+    clint.write_mtimecmp(hartid, true, (val >> u32::BITS) as u32);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+
+    /// 单个硬件线程的 CLINT，记录比较值触发过的中断。
+    struct FakeClint {
+        mtime: Cell<u64>,
+        mtimecmp: Cell<u64>,
+        stip: Cell<bool>,
+        /// 写 `mtimecmp` 的过程中比较值曾经不大于 `mtime`。
+        fired: Cell<bool>,
+    }
+
+    impl FakeClint {
+        fn new(mtime: u64) -> Self {
+            Self {
+                mtime: Cell::new(mtime),
+                mtimecmp: Cell::new(u64::MAX),
+                stip: Cell::new(false),
+                fired: Cell::new(false),
+            }
+        }
+    }
+
+    impl Clint for FakeClint {
+        fn time(&self) -> u64 {
+            self.mtime.get()
+        }
+
+        fn write_mtimecmp(&self, hartid: usize, high: bool, val: u32) {
+            assert_eq!(hartid, 0);
+            let old = self.mtimecmp.get();
+            let new = if high {
+                (old & 0xffff_ffff) | (val as u64) << 32
+            } else {
+                (old & !0xffff_ffff) | val as u64
+            };
+            self.mtimecmp.set(new);
+            if new <= self.mtime.get() {
+                self.fired.set(true);
+            }
+        }
+
+        fn set_stip(&self, pending: bool) {
+            self.stip.set(pending);
+        }
+    }
+
+    #[test]
+    fn future_deadline() {
+        let clint = FakeClint::new(1000);
+        let timer = Timer::new();
+        timer.set(&clint, 0, 2000);
+        assert!(timer.armed(0));
+        assert_eq!(clint.mtimecmp.get(), 2000);
+        assert!(!clint.stip.get());
+        assert!(!clint.fired.get());
+    }
+
+    #[test]
+    fn past_deadline() {
+        let clint = FakeClint::new(1000);
+        let timer = Timer::new();
+        for deadline in [0, 999, 1000] {
+            timer.set(&clint, 0, deadline);
+            assert!(!timer.armed(0));
+            assert_eq!(clint.mtimecmp.get(), u64::MAX);
+            assert!(clint.stip.get());
+        }
+    }
+
+    #[test]
+    fn rearm_without_glitch() {
+        // 低位先变小、高位后变大的写入顺序会短暂产生 0x1_0000_0000，早于当前时间
+        let clint = FakeClint::new(0x1_8000_0000);
+        let timer = Timer::new();
+        timer.set(&clint, 0, 0x1_ffff_ffff);
+        timer.set(&clint, 0, 0x2_0000_0000);
+        assert!(timer.armed(0));
+        assert_eq!(clint.mtimecmp.get(), 0x2_0000_0000);
+        assert!(!clint.fired.get());
+    }
+
+    #[test]
+    fn rearm_clears_pending() {
+        let clint = FakeClint::new(1000);
+        let timer = Timer::new();
+        timer.set(&clint, 0, 500);
+        assert!(clint.stip.get());
+        timer.set(&clint, 0, 1500);
+        assert!(!clint.stip.get());
+        assert!(timer.armed(0));
+        assert_eq!(clint.mtimecmp.get(), 1500);
+    }
+
+    #[test]
+    fn fire_clears_timer() {
+        let clint = FakeClint::new(1000);
+        let timer = Timer::new();
+        timer.set(&clint, 0, 1500);
+        clint.mtime.set(1500);
+        timer.fire(&clint, 0);
+        assert!(!timer.armed(0));
+        assert!(clint.stip.get());
+        assert_eq!(clint.mtimecmp.get(), u64::MAX);
+    }
+
+    #[test]
+    fn harts_are_independent() {
+        let timer = Timer::new();
+        timer.0.fetch_or(1 << 3, Ordering::AcqRel);
+        assert!(timer.armed(3));
+        assert!(!timer.armed(0));
+        timer.disarm(3);
+        assert!(!timer.armed(3));
+    }
+}
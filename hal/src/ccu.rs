@@ -1,4 +1,5 @@
 use super::time::Hz;
+use core::ptr::{read_volatile, write_volatile};
 use d1_pac::ccu::RegisterBlock as CcuRb;
 
 #[derive(Debug)]
@@ -42,3 +43,105 @@ define_gating_reset! {
     UART0: (uart_bgr, uart0_gating, uart0_rst);
     SPI0: (spi_bgr, spi0_gating, spi0_rst);
 }
+
+// FIXME: PLL_CPU, RISCV_CLK and bus clocks are accessed raw, see `ccu@2001000` in nezha.dts
+const CCU_BASE: usize = 0x0200_1000;
+const PLL_CPU_CTRL: *mut u32 = CCU_BASE as _;
+const RISCV_CLK: *mut u32 = (CCU_BASE + 0xd00) as _;
+const PLL_PERI0_CTRL: *const u32 = (CCU_BASE + 0x020) as _;
+const PSI_CLK: *const u32 = (CCU_BASE + 0x510) as _;
+const APB1_CLK: *const u32 = (CCU_BASE + 0x524) as _;
+
+/// Internal low-speed oscillator
+const CLK32K: u32 = 32_768;
+/// Internal RC oscillator, inaccurate
+const RC16M: u32 = 16_000_000;
+/// Clock source of PSI and APB1
+const BUS_SRC_MASK: u32 = 0b11 << 24;
+
+/// Reference clock of PLL_CPU
+pub const HOSC: Hz = Hz(24_000_000);
+
+impl Clocks {
+    /// Reads the PSI and APB1 frequencies configured by earlier boot stages
+    pub fn read() -> Self {
+        let peri = pll_peri_1x();
+        let reg = unsafe { read_volatile(PSI_CLK) };
+        let src = match (reg & BUS_SRC_MASK) >> 24 {
+            0 => HOSC.0,
+            1 => CLK32K,
+            2 => RC16M,
+            _ => peri,
+        };
+        let psi = divide(src, reg, 0b11);
+        let reg = unsafe { read_volatile(APB1_CLK) };
+        let src = match (reg & BUS_SRC_MASK) >> 24 {
+            0 => HOSC.0,
+            1 => CLK32K,
+            2 => psi,
+            _ => peri,
+        };
+        let apb1 = divide(src, reg, 0b1_1111);
+        Self {
+            psi: Hz(psi),
+            apb1: Hz(apb1),
+        }
+    }
+}
+
+/// Divides `src` by `2^N` and `M + 1` of a bus clock register
+#[inline]
+fn divide(src: u32, reg: u32, m_mask: u32) -> u32 {
+    (src >> ((reg >> 8) & 0b11)) / ((reg & m_mask) + 1)
+}
+
+/// Frequency of PLL_PERI(1X), `HOSC * N / M / P0 / 2`
+fn pll_peri_1x() -> u32 {
+    let ctrl = unsafe { read_volatile(PLL_PERI0_CTRL) };
+    let n = ((ctrl & PLL_N_MASK) >> 8) + 1;
+    let m = ((ctrl >> 1) & 1) + 1;
+    let p0 = ((ctrl >> 16) & 0b111) + 1;
+    (HOSC.0 as u64 * n as u64 / (m * p0 * 2) as u64) as u32
+}
+
+const PLL_EN: u32 = 1 << 31;
+const PLL_LDO_EN: u32 = 1 << 30;
+const PLL_LOCK_EN: u32 = 1 << 29;
+const PLL_LOCK: u32 = 1 << 28;
+const PLL_OUTPUT_GATE: u32 = 1 << 27;
+const PLL_N_MASK: u32 = 0xff << 8;
+const PLL_M_MASK: u32 = 0b11;
+
+const CLK_SRC_MASK: u32 = 0b111 << 24;
+const CLK_SRC_HOSC: u32 = 0 << 24;
+const CLK_SRC_PLL_CPU: u32 = 5 << 24;
+
+/// Current multiplier of PLL_CPU, the core runs at `HOSC * n`
+#[inline]
+pub fn cpu_pll_n() -> u32 {
+    let ctrl = unsafe { read_volatile(PLL_CPU_CTRL) };
+    ((ctrl & PLL_N_MASK) >> 8) + 1
+}
+
+/// Reprograms PLL_CPU to `HOSC * n` and runs the core from it
+///
+/// The core is clocked from HOSC while the PLL relocks.
+pub fn set_cpu_pll(n: u32) {
+    assert!((1..=256).contains(&n));
+    unsafe {
+        let clk = read_volatile(RISCV_CLK) & !CLK_SRC_MASK;
+        write_volatile(RISCV_CLK, clk | CLK_SRC_HOSC);
+
+        let ctrl = read_volatile(PLL_CPU_CTRL)
+            & !(PLL_N_MASK | PLL_M_MASK | PLL_LOCK_EN | PLL_OUTPUT_GATE);
+        let ctrl = ctrl | PLL_EN | PLL_LDO_EN | (n - 1) << 8;
+        write_volatile(PLL_CPU_CTRL, ctrl);
+        write_volatile(PLL_CPU_CTRL, ctrl | PLL_LOCK_EN);
+        while read_volatile(PLL_CPU_CTRL) & PLL_LOCK == 0 {
+            core::hint::spin_loop();
+        }
+        write_volatile(PLL_CPU_CTRL, ctrl | PLL_LOCK_EN | PLL_OUTPUT_GATE);
+
+        write_volatile(RISCV_CLK, clk | CLK_SRC_PLL_CPU);
+    }
+}
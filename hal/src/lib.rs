@@ -5,6 +5,8 @@ pub mod gpio;
 pub mod plic;
 pub mod spi;
 pub mod time;
+pub mod uart;
+pub mod wdt;
 pub use d1_pac as pac;
 
 #[allow(clippy::transmutes_expressible_as_ptr_casts)]
@@ -0,0 +1,116 @@
+//! Universal Asynchronous Receiver-Transmitter (UART)
+//!
+//! 16550-compatible, with 64-byte TX and RX FIFOs. Pins are configured by BROM for UART0,
+//! so this driver only deals with the controller itself.
+
+use super::{
+    ccu::{Clocks, Gating, Reset},
+    time::{Bps, Hz},
+};
+use core::convert::Infallible;
+use d1_pac::{uart::RegisterBlock, CCU};
+
+// Line Control Register
+const LCR_DLS_8: u32 = 0b11;
+const LCR_DLAB: u32 = 1 << 7;
+// FIFO Control Register
+const FCR_FIFOE: u32 = 1 << 0;
+const FCR_RFIFOR: u32 = 1 << 1;
+const FCR_XFIFOR: u32 = 1 << 2;
+// UART Status Register
+const USR_BUSY: u32 = 1 << 0;
+const USR_TFNF: u32 = 1 << 1;
+const USR_TFE: u32 = 1 << 2;
+const USR_RFNE: u32 = 1 << 3;
+
+/// D1 UART peripheral
+pub struct Uart<UART: Instance> {
+    inner: UART,
+}
+
+impl<UART: Instance> Uart<UART> {
+    /// Resets the controller and configures it to 8N1 at `baud`, clocked from APB1
+    ///
+    /// Both FIFOs are enabled and flushed, interrupts are disabled.
+    pub fn new(uart: UART, baud: Bps, clocks: &Clocks) -> Self {
+        let (Bps(baud), Hz(apb1)) = (baud, clocks.apb1);
+        let divisor = ((apb1 + 8 * baud) / (16 * baud)).clamp(1, 0xffff);
+
+        // note(unsafe): async read and write using ccu registers
+        let ccu = unsafe { &*CCU::ptr() };
+        UART::gating_pass(ccu);
+        UART::deassert_reset(ccu);
+
+        let this = Self { inner: uart };
+        // LCR can not be written while the controller is busy
+        while this.inner.usr.read().bits() & (USR_BUSY | USR_TFE) != USR_TFE {
+            core::hint::spin_loop();
+        }
+        let uart = &this.inner;
+        uart.ier().write(|w| unsafe { w.bits(0) });
+        uart.lcr.write(|w| unsafe { w.bits(LCR_DLS_8 | LCR_DLAB) });
+        uart.dll().write(|w| unsafe { w.bits(divisor & 0xff) });
+        uart.dlh().write(|w| unsafe { w.bits(divisor >> 8) });
+        uart.lcr.write(|w| unsafe { w.bits(LCR_DLS_8) });
+        #[rustfmt::skip]
+        uart.fcr().write(|w| unsafe { w.bits(
+            FCR_FIFOE | FCR_RFIFOR | FCR_XFIFOR
+        ) });
+        this
+    }
+
+    /// Uses a controller configured by an earlier boot stage as is
+    #[inline]
+    pub const fn attach(uart: UART) -> Self {
+        Self { inner: uart }
+    }
+
+    /// Reads a byte from the RX FIFO
+    #[inline]
+    pub fn read(&self) -> nb::Result<u8, Infallible> {
+        if self.inner.usr.read().bits() & USR_RFNE == 0 {
+            Err(nb::Error::WouldBlock)
+        } else {
+            Ok(self.inner.rbr().read().rbr().bits())
+        }
+    }
+
+    /// Writes a byte into the TX FIFO
+    #[inline]
+    pub fn write(&self, byte: u8) -> nb::Result<(), Infallible> {
+        if self.inner.usr.read().bits() & USR_TFNF == 0 {
+            Err(nb::Error::WouldBlock)
+        } else {
+            self.inner.thr().write(|w| w.thr().variant(byte));
+            Ok(())
+        }
+    }
+
+    /// Waits until the TX FIFO is empty
+    #[inline]
+    pub fn flush(&self) -> nb::Result<(), Infallible> {
+        if self.inner.usr.read().bits() & USR_TFE == 0 {
+            Err(nb::Error::WouldBlock)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Writes a byte, waiting for room in the TX FIFO
+    #[inline]
+    pub fn write_byte(&self, byte: u8) {
+        while self.write(byte).is_err() {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Release peripheral
+    #[inline]
+    pub fn free(self) -> UART {
+        self.inner
+    }
+}
+
+pub trait Instance: Gating + Reset + core::ops::Deref<Target = RegisterBlock> {}
+
+impl Instance for d1_pac::UART0 {}
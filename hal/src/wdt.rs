@@ -0,0 +1,32 @@
+//! Watchdog (WDT)
+
+use core::ptr::{read_volatile, write_volatile};
+
+// FIXME: not covered by d1-pac, see `watchdog@6011000` in nezha.dts
+const WDT_BASE: usize = 0x0601_1000;
+const WDOG_CFG: *mut u32 = (WDT_BASE + 0x14) as _;
+const WDOG_MODE: *mut u32 = (WDT_BASE + 0x18) as _;
+
+/// Every write to configuration registers must carry this key
+const KEY: u32 = 0x16aa << 16;
+const CFG_MASK: u32 = 0b11;
+/// Reset the whole system when the watchdog fires
+const CFG_SYSTEM: u32 = 0b01;
+const MODE_INTV_MASK: u32 = 0xf << 4;
+const MODE_EN: u32 = 1;
+
+/// Resets the whole system through the watchdog
+///
+/// The watchdog is armed with its shortest interval (0.5 s) and never fed.
+#[inline]
+pub fn reset_system() -> ! {
+    unsafe {
+        let cfg = read_volatile(WDOG_CFG) & !(CFG_MASK | (0xffff << 16));
+        write_volatile(WDOG_CFG, cfg | CFG_SYSTEM | KEY);
+        let mode = read_volatile(WDOG_MODE) & !(MODE_INTV_MASK | (0xffff << 16));
+        write_volatile(WDOG_MODE, mode | MODE_EN | KEY);
+    }
+    loop {
+        core::hint::spin_loop();
+    }
+}
@@ -1,25 +1,97 @@
 fn main() {
     use std::{env, fs, path::PathBuf};
 
-    let ld = &PathBuf::from(env::var_os("OUT_DIR").unwrap()).join("see.ld");
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    let ld = &out.join("see.ld");
     fs::write(ld, LINKER).unwrap();
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-env-changed=LOG");
+    println!("cargo:rerun-if-env-changed=SEE_SYMBOLS");
     println!("cargo:rustc-link-arg=-T{}", ld.display());
+
+    // 符号表来自上一次构建的 `objdump -t -C` 输出，没有时生成空表
+    let dump = match env::var_os("SEE_SYMBOLS") {
+        Some(path) => {
+            println!("cargo:rerun-if-changed={}", path.to_string_lossy());
+            fs::read_to_string(path).unwrap_or_default()
+        }
+        None => String::new(),
+    };
+    let mut table = symbols(&dump);
+    assert!(
+        table.len() <= SYMBOLS_SIZE,
+        "symbol table takes {} bytes, more than the {SYMBOLS_SIZE} reserved",
+        table.len(),
+    );
+    table.resize(SYMBOLS_SIZE, 0);
+    fs::write(out.join("symbols.bin"), table).unwrap();
+}
+
+/// 为符号表保留的空间。
+///
+/// 符号表的长度固定，重新嵌入符号表不会移动其他段，两次构建的符号地址相同。
+const SYMBOLS_SIZE: usize = 64 << 10;
+
+/// 从 `objdump -t` 的输出中提取函数符号，编码成紧凑的符号表。
+///
+/// 符号表由按地址排序的条目和名字组成，每个条目是 4 个小端 u32：
+/// 地址、大小、名字的偏移和名字的长度。表头是条目数。
+fn symbols(dump: &str) -> Vec<u8> {
+    // 00000000400000a4 l     F .text\t0000000000000036 rust_main
+    let mut symbols = dump
+        .lines()
+        .filter_map(|line| {
+            let (head, tail) = line.split_once('\t')?;
+            let mut head = head.split_whitespace();
+            let addr = u32::from_str_radix(head.next()?, 16).ok()?;
+            if head.last()? != ".text" || !line.contains(" F ") {
+                return None;
+            }
+            let (size, name) = tail.trim().split_once(' ')?;
+            let size = u32::from_str_radix(size, 16).ok()?;
+            Some((addr, size, strip_hash(name.trim())))
+        })
+        .collect::<Vec<_>>();
+    symbols.sort_unstable();
+    symbols.dedup_by_key(|(addr, _, _)| *addr);
+
+    let mut table = Vec::new();
+    let mut names = Vec::new();
+    table.extend_from_slice(&(symbols.len() as u32).to_le_bytes());
+    for (addr, size, name) in symbols {
+        for word in [addr, size, names.len() as u32, name.len() as u32] {
+            table.extend_from_slice(&word.to_le_bytes());
+        }
+        names.extend_from_slice(name.as_bytes());
+    }
+    table.extend(names);
+    table
+}
+
+/// 去掉旧式修饰名末尾的哈希。
+fn strip_hash(name: &str) -> &str {
+    match name.rsplit_once("::h") {
+        Some((path, hash)) if hash.len() == 16 && hash.bytes().all(|c| c.is_ascii_hexdigit()) => {
+            path
+        }
+        _ => name,
+    }
 }
 
 const LINKER: &[u8] = b"
 OUTPUT_ARCH(riscv)
 ENTRY(_start)
 MEMORY {
-    DDR : ORIGIN = 0x40000000, LENGTH = 2M
+    DDR : ORIGIN = 0x40000000, LENGTH = 2M - 8K
 }
 SECTIONS {
     .text : {
+        stext = .;
         *(.text.entry)
         . = ALIGN(4);
         *(.text.trap_handler)
         *(.text .text.*)
+        etext = .;
     } > DDR
     .rodata : {
         *(.rodata .rodata.*)
@@ -30,6 +102,12 @@ SECTIONS {
         *(.sdata .sdata.*)
     } > DDR
     sidata = LOADADDR(.data);
+    /* fixed size, placed before .bss so that the image does not contain .bss */
+    .symbols : {
+        ssymbols = .;
+        KEEP(*(.symbols))
+        esymbols = .;
+    } > DDR
     .bss (NOLOAD) : {
         *(.bss.uninit)
         . = ALIGN(8);
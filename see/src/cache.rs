@@ -0,0 +1,117 @@
+//! 平头哥 C906 缓存维护，作为厂商扩展提供给特权软件。
+//!
+//! C906 的数据缓存和多数 DMA 主设备不一致，维护缓存要用平头哥的扩展指令。
+//! 特权软件通过这个扩展按物理地址维护缓存，不需要知道扩展指令和 `mxstatus` 的设置。
+//!
+//! 扩展指令以机器码写出，不依赖汇编器的支持。
+
+use core::{arch::asm, ops::Range};
+use rustsbi::spec::binary::SbiRet;
+
+/// 厂商扩展号，`0x0900_0000` 加上平头哥的 `mvendorid`。
+pub(crate) const EID_CACHE: usize = 0x0900_0000 + 0x5b7;
+
+/// 写回 `[a0, a0 + a1)` 中的脏行。
+pub(crate) const CLEAN_RANGE: usize = 0;
+/// 丢弃 `[a0, a0 + a1)` 中的缓存行。
+pub(crate) const INVALIDATE_RANGE: usize = 1;
+/// 写回并丢弃 `[a0, a0 + a1)` 中的缓存行。
+pub(crate) const FLUSH_RANGE: usize = 2;
+/// 写回整个数据缓存。
+pub(crate) const CLEAN_ALL: usize = 3;
+/// 写回并丢弃整个数据缓存。
+pub(crate) const FLUSH_ALL: usize = 4;
+
+const LINE_SIZE: usize = 64;
+
+/// 扩展指令是否可用，不可用时扩展不存在。
+#[inline]
+pub(crate) fn available() -> bool {
+    use crate::riscv_spec::mxstatus;
+    mxstatus::read() & mxstatus::THEADISAEE != 0
+}
+
+/// 写回范围内的脏行。
+pub(crate) fn clean(range: Range<usize>) -> SbiRet {
+    for line in lines(&range) {
+        dcache_cpa(line);
+    }
+    sync_s();
+    SbiRet::success(0)
+}
+
+/// 丢弃范围内的缓存行。
+///
+/// 部分覆盖的行里还有范围外的数据，这些行写回后再丢弃。
+pub(crate) fn invalidate(range: Range<usize>) -> SbiRet {
+    for line in lines(&range) {
+        if line < range.start || range.end < line + LINE_SIZE {
+            dcache_cipa(line);
+        } else {
+            dcache_ipa(line);
+        }
+    }
+    sync_s();
+    SbiRet::success(0)
+}
+
+/// 写回并丢弃范围内的缓存行。
+pub(crate) fn flush(range: Range<usize>) -> SbiRet {
+    for line in lines(&range) {
+        dcache_cipa(line);
+    }
+    sync_s();
+    SbiRet::success(0)
+}
+
+/// 写回整个数据缓存。
+pub(crate) fn clean_all() -> SbiRet {
+    // dcache.call
+    unsafe { asm!(".word 0x0010000b") };
+    sync_s();
+    SbiRet::success(0)
+}
+
+/// 写回并丢弃整个数据缓存。
+///
+/// 不提供只丢弃整个缓存的操作，那会丢掉 SEE 自己的脏数据。
+pub(crate) fn flush_all() -> SbiRet {
+    // dcache.ciall
+    unsafe { asm!(".word 0x0030000b") };
+    sync_s();
+    SbiRet::success(0)
+}
+
+/// 范围覆盖的每个缓存行的起始地址。
+#[inline]
+fn lines(range: &Range<usize>) -> impl Iterator<Item = usize> {
+    let start = range.start & !(LINE_SIZE - 1);
+    let end = if range.is_empty() { start } else { range.end };
+    (start..end).step_by(LINE_SIZE)
+}
+
+/// dcache.cpa a0
+#[inline(always)]
+fn dcache_cpa(addr: usize) {
+    unsafe { asm!(".word 0x0295000b", in("a0") addr) };
+}
+
+/// dcache.ipa a0
+#[inline(always)]
+fn dcache_ipa(addr: usize) {
+    unsafe { asm!(".word 0x02a5000b", in("a0") addr) };
+}
+
+/// dcache.cipa a0
+#[inline(always)]
+fn dcache_cipa(addr: usize) {
+    unsafe { asm!(".word 0x02b5000b", in("a0") addr) };
+}
+
+/// 等待之前的缓存操作在所有核上完成。
+///
+/// sync.s
+#[inline(always)]
+fn sync_s() {
+    unsafe { asm!(".word 0x0190000b") };
+}
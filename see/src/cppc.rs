@@ -0,0 +1,144 @@
+//! 协同处理器性能控制。
+//!
+//! 性能以 PLL_CPU 的倍频为单位，1 个单位是 24 MHz。
+//! 参考计数器是 `time`，恰好以 1 个单位的频率计数；交付计数器是 `cycle`。
+
+use core::arch::asm;
+use hal::ccu;
+use rustsbi::spec::binary::SbiRet;
+
+/// 最高性能，对应 nezha.dts 中唯一的 opp 1008 MHz。
+const HIGHEST: u32 = 42;
+/// 最低性能，对应 BROM 设置的 408 MHz。
+const LOWEST: u32 = 17;
+/// 重新锁定 PLL 的延迟，纳秒，与 nezha.dts 中的 `clock-latency-ns` 一致。
+const TRANSITION_LATENCY: u32 = 244144;
+
+const HIGHEST_PERFORMANCE: usize = 0x00;
+const NOMINAL_PERFORMANCE: usize = 0x01;
+const LOWEST_NONLINEAR_PERFORMANCE: usize = 0x02;
+const LOWEST_PERFORMANCE: usize = 0x03;
+const DESIRED_PERFORMANCE: usize = 0x05;
+const MINIMUM_PERFORMANCE: usize = 0x06;
+const MAXIMUM_PERFORMANCE: usize = 0x07;
+const REFERENCE_PERFORMANCE_COUNTER: usize = 0x0b;
+const DELIVERED_PERFORMANCE_COUNTER: usize = 0x0c;
+const REFERENCE_PERFORMANCE: usize = 0x12;
+const LOWEST_FREQUENCY: usize = 0x13;
+const NOMINAL_FREQUENCY: usize = 0x14;
+const LAST_STANDARD: usize = NOMINAL_FREQUENCY;
+const TRANSITION_LATENCY_REG: usize = 0x8000_0000;
+
+/// 特权软件设置的性能范围。
+struct Limits {
+    min: u32,
+    max: u32,
+    desired: u32,
+}
+
+static mut LIMITS: Limits = Limits {
+    min: LOWEST,
+    max: HIGHEST,
+    desired: 0,
+};
+
+/// 寄存器宽度，不支持的寄存器返回 `None`。
+fn width(reg: usize) -> Result<Option<usize>, SbiRet> {
+    match reg {
+        REFERENCE_PERFORMANCE_COUNTER | DELIVERED_PERFORMANCE_COUNTER => Ok(Some(64)),
+        HIGHEST_PERFORMANCE
+        | NOMINAL_PERFORMANCE
+        | LOWEST_NONLINEAR_PERFORMANCE
+        | LOWEST_PERFORMANCE
+        | DESIRED_PERFORMANCE
+        | MINIMUM_PERFORMANCE
+        | MAXIMUM_PERFORMANCE
+        | REFERENCE_PERFORMANCE
+        | LOWEST_FREQUENCY
+        | NOMINAL_FREQUENCY
+        | TRANSITION_LATENCY_REG => Ok(Some(32)),
+        0..=LAST_STANDARD => Ok(None),
+        _ => Err(SbiRet::invalid_param()),
+    }
+}
+
+pub(crate) fn probe(reg: usize) -> SbiRet {
+    match width(reg) {
+        Ok(width) => SbiRet::success(width.unwrap_or(0)),
+        Err(e) => e,
+    }
+}
+
+pub(crate) fn read(reg: usize) -> SbiRet {
+    match width(reg) {
+        Ok(Some(_)) => {}
+        Ok(None) => return SbiRet::not_supported(),
+        Err(e) => return e,
+    }
+    let limits = unsafe { &LIMITS };
+    let value = match reg {
+        HIGHEST_PERFORMANCE | NOMINAL_PERFORMANCE => HIGHEST as usize,
+        LOWEST_NONLINEAR_PERFORMANCE | LOWEST_PERFORMANCE => LOWEST as usize,
+        DESIRED_PERFORMANCE => limits.desired as usize,
+        MINIMUM_PERFORMANCE => limits.min as usize,
+        MAXIMUM_PERFORMANCE => limits.max as usize,
+        REFERENCE_PERFORMANCE_COUNTER => riscv::register::time::read(),
+        DELIVERED_PERFORMANCE_COUNTER => {
+            let bits: usize;
+            unsafe { asm!("csrr {}, mcycle", out(reg) bits, options(nomem)) };
+            bits
+        }
+        REFERENCE_PERFORMANCE => 1,
+        LOWEST_FREQUENCY => mhz(LOWEST),
+        NOMINAL_FREQUENCY => mhz(HIGHEST),
+        TRANSITION_LATENCY_REG => TRANSITION_LATENCY as usize,
+        _ => unreachable!(),
+    };
+    SbiRet::success(value)
+}
+
+/// RV64 上寄存器的值都能用 [`read`] 一次读出，高位总是 0。
+pub(crate) fn read_hi(reg: usize) -> SbiRet {
+    match width(reg) {
+        Ok(Some(_)) => SbiRet::success(0),
+        Ok(None) => SbiRet::not_supported(),
+        Err(e) => e,
+    }
+}
+
+pub(crate) fn write(reg: usize, value: u64) -> SbiRet {
+    match width(reg) {
+        Ok(Some(_)) => {}
+        Ok(None) => return SbiRet::not_supported(),
+        Err(e) => return e,
+    }
+    let limits = unsafe { &mut LIMITS };
+    let Ok(value) = u32::try_from(value) else {
+        return SbiRet::invalid_param();
+    };
+    match reg {
+        DESIRED_PERFORMANCE => limits.desired = value,
+        MINIMUM_PERFORMANCE if (LOWEST..=limits.max).contains(&value) => limits.min = value,
+        MAXIMUM_PERFORMANCE if (limits.min..=HIGHEST).contains(&value) => limits.max = value,
+        MINIMUM_PERFORMANCE | MAXIMUM_PERFORMANCE => return SbiRet::invalid_param(),
+        _ => return SbiRet::denied(),
+    }
+    apply(limits);
+    SbiRet::success(0)
+}
+
+/// 按期望性能设置 PLL_CPU，期望为 0 表示由固件选择，用最高性能。
+fn apply(limits: &Limits) {
+    let target = match limits.desired {
+        0 => limits.max,
+        desired => desired.clamp(limits.min, limits.max),
+    };
+    if target != ccu::cpu_pll_n() {
+        ccu::set_cpu_pll(target);
+    }
+}
+
+#[inline]
+fn mhz(perf: u32) -> usize {
+    (perf * (ccu::HOSC.0 / 1_000_000)) as usize
+}
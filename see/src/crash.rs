@@ -0,0 +1,205 @@
+//! 崩溃记录。
+//!
+//! 固件崩溃时把现场写入主存中固定的一页，看门狗复位后 SPL 不会覆盖这一页，
+//! 下次启动时 SEE 打印并清除记录。
+
+use crate::{unwind, xreg};
+use common::memory::{CRASH, CRASH_SIZE};
+use core::fmt::{self, Write};
+use fast_trap::FlowContext;
+
+const MAGIC: u32 = u32::from_le_bytes(*b"CRSH");
+const MAX_FRAMES: usize = 16;
+const MAX_MESSAGE: usize = 512;
+
+/// 表示崩溃原因是 panic 而不是陷入。
+const CAUSE_PANIC: usize = !0;
+
+/// 保存在主存中的崩溃现场。
+#[repr(C)]
+struct Record {
+    magic: u32,
+    /// 除 `magic` 和 `checksum` 外所有字的和。
+    checksum: u32,
+    mcause: usize,
+    mepc: usize,
+    mtval: usize,
+    mstatus: usize,
+    /// `x0` 的位置不使用。
+    regs: [usize; 32],
+    n_frames: usize,
+    frames: [usize; MAX_FRAMES],
+    message_len: usize,
+    message: [u8; MAX_MESSAGE],
+}
+
+const _: () = assert!(core::mem::size_of::<Record>() <= CRASH_SIZE);
+
+/// 本次崩溃是否已经记录了陷入现场。
+static mut TRAPPED: bool = false;
+
+#[inline]
+fn record() -> &'static mut Record {
+    unsafe { &mut *(CRASH as *mut Record) }
+}
+
+/// 记录 M 态陷入的现场，`ctx` 必须是完整路径的上下文。
+pub(crate) fn trap(ctx: &FlowContext, mcause: usize, mtval: usize) {
+    use crate::riscv_spec::{mepc, mstatus};
+
+    let rec = record();
+    rec.mcause = mcause;
+    rec.mepc = mepc::read();
+    rec.mtval = mtval;
+    rec.mstatus = mstatus::read();
+    for (i, reg) in rec.regs.iter_mut().enumerate() {
+        *reg = xreg::get(ctx, i);
+    }
+    rec.n_frames = backtrace(&mut rec.frames, rec.mepc, ctx.ra, ctx.s[0]);
+    rec.message_len = 0;
+    unsafe { TRAPPED = true };
+    seal(rec);
+}
+
+/// 记录 panic 信息，没有陷入现场时记录 panic 处的寄存器。
+pub(crate) fn panic(info: &core::panic::PanicInfo) {
+    let rec = record();
+    if !unsafe { TRAPPED } {
+        let (ra, sp, fp): (usize, usize, usize);
+        unsafe {
+            core::arch::asm!(
+                "mv {ra}, ra",
+                "mv {sp}, sp",
+                "mv {fp}, s0",
+                ra = out(reg) ra,
+                sp = out(reg) sp,
+                fp = out(reg) fp,
+            )
+        };
+        rec.mcause = CAUSE_PANIC;
+        rec.mepc = 0;
+        rec.mtval = 0;
+        rec.mstatus = crate::riscv_spec::mstatus::read();
+        rec.regs = [0; 32];
+        rec.regs[1] = ra;
+        rec.regs[2] = sp;
+        rec.regs[8] = fp;
+        rec.n_frames = backtrace(&mut rec.frames, ra, 0, fp);
+    }
+    let mut message = Message(0);
+    let _ = write!(message, "{info}");
+    rec.message_len = message.0;
+    seal(rec);
+}
+
+/// 本次崩溃回溯到的调用栈。
+pub(crate) fn frames() -> &'static [usize] {
+    let rec = record();
+    &rec.frames[..rec.n_frames]
+}
+
+/// 本次崩溃记录的寄存器和 `pc`，panic 时 `pc` 是 panic 处的返回地址。
+#[cfg(feature = "gdb-stub")]
+pub(crate) fn context() -> ([usize; 32], usize) {
+    let rec = record();
+    let pc = match rec.mcause {
+        CAUSE_PANIC => rec.regs[1],
+        _ => rec.mepc,
+    };
+    (rec.regs, pc)
+}
+
+/// 打印并清除上次启动留下的崩溃记录。
+pub(crate) fn report() {
+    let rec = record();
+    if rec.magic != MAGIC || rec.checksum != checksum(rec) {
+        return;
+    }
+    println!("[rustsbi] crash report from last boot");
+    println!("{rec}");
+    rec.magic = 0;
+}
+
+fn seal(rec: &mut Record) {
+    rec.checksum = checksum(rec);
+    rec.magic = MAGIC;
+}
+
+fn checksum(rec: &Record) -> u32 {
+    let words = unsafe {
+        core::slice::from_raw_parts(
+            rec as *const _ as *const u32,
+            core::mem::size_of::<Record>() / 4,
+        )
+    };
+    words[2..].iter().fold(0u32, |sum, w| sum.wrapping_add(*w))
+}
+
+/// 回溯调用栈，返回记录的帧数。
+fn backtrace(frames: &mut [usize], pc: usize, ra: usize, fp: usize) -> usize {
+    frames
+        .iter_mut()
+        .zip(unwind::walk(pc, ra, fp))
+        .map(|(frame, addr)| *frame = addr)
+        .count()
+}
+
+/// 把格式化的 panic 信息截断写入记录。
+struct Message(usize);
+
+impl Write for Message {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let rec = record();
+        let len = s.len().min(MAX_MESSAGE - self.0);
+        rec.message[self.0..][..len].copy_from_slice(&s.as_bytes()[..len]);
+        self.0 += len;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const NAMES: [&str; 32] = [
+            "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3",
+            "a4", "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11",
+            "t3", "t4", "t5", "t6",
+        ];
+
+        writeln!(f, "-----------------------------")?;
+        if self.mcause == CAUSE_PANIC {
+            writeln!(f, "> cause:   panic")?;
+        } else {
+            writeln!(f, "> mcause:  {:#018x}", self.mcause)?;
+            writeln!(f, "> mepc:    {:#018x}", self.mepc)?;
+            writeln!(f, "> mtval:   {:#018x}", self.mtval)?;
+        }
+        writeln!(f, "> mstatus: {:#018x}", self.mstatus)?;
+        for i in (0..32).step_by(2) {
+            writeln!(
+                f,
+                "> {:<4} {:#018x}  {:<4} {:#018x}",
+                NAMES[i],
+                self.regs[i],
+                NAMES[i + 1],
+                self.regs[i + 1]
+            )?;
+        }
+        writeln!(f, "> backtrace:")?;
+        for (i, addr) in self.frames[..self.n_frames.min(MAX_FRAMES)]
+            .iter()
+            .enumerate()
+        {
+            writeln!(f, ">   #{i:<2} {addr:#018x}")?;
+        }
+        let message = &self.message[..self.message_len.min(MAX_MESSAGE)];
+        if !message.is_empty() {
+            // 截断可能切开多字节字符，只打印完整的部分
+            let message = match core::str::from_utf8(message) {
+                Ok(s) => s,
+                Err(e) => unsafe { core::str::from_utf8_unchecked(&message[..e.valid_up_to()]) },
+            };
+            writeln!(f, "> {message}")?;
+        }
+        write!(f, "-----------------------------")
+    }
+}
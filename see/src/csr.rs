@@ -0,0 +1,45 @@
+//! 模拟特权软件不能直接访问的 CSR。
+//!
+//! 模拟的 CSR 都是只读的：`time`，以及平头哥扩展的几个 M 态 CSR。
+//! RV64 上没有 `timeh`，访问它仍然是非法指令。
+
+use common::csr::decode;
+use core::arch::asm;
+
+const TIME: usize = 0xc01;
+const MXSTATUS: usize = 0x7c0;
+const MHCR: usize = 0x7c1;
+const MHINT: usize = 0x7c5;
+const MCPUID: usize = 0xfc0;
+const MAPBADDR: usize = 0xfc1;
+
+/// 模拟一条 CSR 指令，返回目标寄存器和要写入的值。
+///
+/// 不是模拟的 CSR 或试图写只读的 CSR 返回 `None`，应作为非法指令交给特权软件。
+pub(crate) fn emulate(insn: usize) -> Option<(usize, usize)> {
+    let insn = decode(insn)?;
+    if insn.writes() {
+        return None;
+    }
+    read(insn.csr).map(|val| (insn.rd, val))
+}
+
+/// 读模拟的 CSR。
+fn read(csr: usize) -> Option<usize> {
+    macro_rules! csrr {
+        ($csr:expr) => {{
+            let bits: usize;
+            unsafe { asm!("csrr {}, {csr}", out(reg) bits, csr = const $csr, options(nomem)) };
+            bits
+        }};
+    }
+    match csr {
+        TIME => Some(csrr!(TIME)),
+        MXSTATUS => Some(csrr!(MXSTATUS)),
+        MHCR => Some(csrr!(MHCR)),
+        MHINT => Some(csrr!(MHINT)),
+        MCPUID => Some(csrr!(MCPUID)),
+        MAPBADDR => Some(csrr!(MAPBADDR)),
+        _ => None,
+    }
+}
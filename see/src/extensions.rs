@@ -1,22 +1,34 @@
+use crate::{cache, cppc, hsm::HART, legacy, pmu, timer, Supervisor, SUPERVISOR};
 use aclint::SifiveClint as Clint;
-use core::{convert::Infallible, mem::MaybeUninit};
+use core::{arch::asm, iter::StepBy, mem::MaybeUninit, ops::Range};
 use hal::CLINT_BASE;
-use riscv::register::mip;
+use rcore_console::Console as _;
 use rustsbi::{spec::binary::SbiRet, HartMask, RustSBI};
+use sbi_spec::{base, cppc as spec_cppc, dbcn, susp};
 
 static mut SBI: MaybeUninit<FixedRustSBI> = MaybeUninit::uninit();
 
+/// 特权软件可以访问的物理内存。
+static mut SUPERVISOR_MEMORY: Range<usize> = 0..0;
+
 pub(crate) struct Impl;
 pub(crate) type FixedRustSBI<'a> =
-    RustSBI<&'a Impl, &'a Impl, Infallible, Infallible, &'a Impl, Infallible>;
+    RustSBI<&'a Impl, &'a Impl, &'a Impl, &'a Impl, &'a Impl, &'a Impl>;
 
-pub(crate) fn init() {
+/// 初始化 SBI 实现。
+///
+/// `mem` 是主存范围，其中 `mem.start..kernel` 是 PMP 保护的 SBI 区域。
+pub(crate) fn init(mem: Range<usize>, kernel: usize) {
     unsafe {
+        SUPERVISOR_MEMORY = kernel..mem.end;
         SBI = MaybeUninit::new(
             rustsbi::Builder::new_machine()
                 .with_timer(&Impl)
                 .with_ipi(&Impl)
+                .with_fence(&Impl)
+                .with_hsm(&Impl)
                 .with_reset(&Impl)
+                .with_pmu(&Impl)
                 .build(),
         )
     }
@@ -27,39 +39,402 @@ pub(crate) fn sbi<'a>() -> &'static mut FixedRustSBI<'a> {
     unsafe { SBI.assume_init_mut() }
 }
 
+/// 处理 SBI 调用。
+///
+/// RustSBI 没有提供的扩展在这里分发，其他的转交给 RustSBI。
+pub(crate) fn handle_ecall(extension: usize, function: usize, param: [usize; 6]) -> SbiRet {
+    match extension {
+        dbcn::EID_DBCN => debug_console(function, param),
+        susp::EID_SUSP => system_suspend(function, param),
+        spec_cppc::EID_CPPC => match function {
+            spec_cppc::PROBE => cppc::probe(param[0]),
+            spec_cppc::READ => cppc::read(param[0]),
+            spec_cppc::READ_HI => cppc::read_hi(param[0]),
+            spec_cppc::WRITE => cppc::write(param[0], param[1] as _),
+            _ => SbiRet::not_supported(),
+        },
+        cache::EID_CACHE if cache::available() => cache_maintenance(function, param),
+        base::EID_BASE if function == base::PROBE_EXTENSION && probe(param[0]) => {
+            SbiRet::success(1)
+        }
+        _ => sbi().handle_ecall(extension, function, param),
+    }
+}
+
+/// 探测 RustSBI 之外实现的扩展。
+#[inline]
+fn probe(extension: usize) -> bool {
+    matches!(
+        extension,
+        dbcn::EID_DBCN | susp::EID_SUSP | spec_cppc::EID_CPPC
+    ) || legacy::probe(extension)
+        || (extension == cache::EID_CACHE && cache::available())
+}
+
+/// Debug Console 扩展。
+fn debug_console(function: usize, [num_bytes, base_lo, base_hi, ..]: [usize; 6]) -> SbiRet {
+    match function {
+        dbcn::CONSOLE_WRITE => match supervisor_buffer(num_bytes, base_lo, base_hi) {
+            Some(buf) => {
+                let buf = unsafe { core::slice::from_raw_parts(buf.start as *const u8, buf.len()) };
+                for c in buf {
+                    crate::Console.put_char(*c);
+                }
+                SbiRet::success(buf.len())
+            }
+            None => SbiRet::invalid_param(),
+        },
+        dbcn::CONSOLE_READ => match supervisor_buffer(num_bytes, base_lo, base_hi) {
+            Some(buf) => {
+                let buf =
+                    unsafe { core::slice::from_raw_parts_mut(buf.start as *mut u8, buf.len()) };
+                let mut len = 0;
+                for c in buf {
+                    match crate::Console.get_char() {
+                        Some(ch) => *c = ch,
+                        None => break,
+                    }
+                    len += 1;
+                }
+                SbiRet::success(len)
+            }
+            None => SbiRet::invalid_param(),
+        },
+        dbcn::CONSOLE_WRITE_BYTE => {
+            crate::Console.put_char(num_bytes as u8);
+            SbiRet::success(0)
+        }
+        _ => SbiRet::not_supported(),
+    }
+}
+
+/// 缓存维护厂商扩展。
+///
+/// 按地址的操作只能作用于特权软件可访问的主存。
+fn cache_maintenance(function: usize, [base, size, ..]: [usize; 6]) -> SbiRet {
+    let range = || supervisor_buffer(size, base, 0);
+    match function {
+        cache::CLEAN_RANGE => range().map_or_else(SbiRet::invalid_address, cache::clean),
+        cache::INVALIDATE_RANGE => range().map_or_else(SbiRet::invalid_address, cache::invalidate),
+        cache::FLUSH_RANGE => range().map_or_else(SbiRet::invalid_address, cache::flush),
+        cache::CLEAN_ALL => cache::clean_all(),
+        cache::FLUSH_ALL => cache::flush_all(),
+        _ => SbiRet::not_supported(),
+    }
+}
+
+/// System Suspend 扩展。
+///
+/// 挂起到内存时主存保持供电，SEE 停在 `wfi` 等待唤醒，然后像启动一样从 `resume_addr` 进入特权软件。
+fn system_suspend(function: usize, [sleep_type, resume_addr, opaque, ..]: [usize; 6]) -> SbiRet {
+    const SUSPEND_TO_RAM: u32 = 0;
+    if function != susp::SUSPEND {
+        return SbiRet::not_supported();
+    }
+    // 保留的和平台定义的睡眠类型都没有实现
+    if sleep_type as u32 != SUSPEND_TO_RAM {
+        return SbiRet::invalid_param();
+    }
+    if !unsafe { SUPERVISOR_MEMORY.contains(&resume_addr) } {
+        return SbiRet::invalid_address();
+    }
+    if HART.suspend().is_err() {
+        return SbiRet::denied();
+    }
+    wait_for_wakeup();
+    unsafe {
+        SUPERVISOR = Supervisor {
+            start_addr: resume_addr,
+            opaque,
+        }
+    };
+    let _ = HART.resume(false);
+    SbiRet::success(0)
+}
+
+/// 只开放唤醒源，等到其中之一挂起。
+///
+/// 唤醒源是软件中断、已设置的定时器和特权软件在 PLIC 上使能的外部中断。
+fn wait_for_wakeup() {
+    use crate::riscv_spec::mie;
+    let saved = riscv::register::mie::read().bits();
+    let mut wake = mie::MSIE | mie::SEIE;
+    if timer::armed(0) {
+        wake |= mie::MTIE;
+    }
+    mie::write(wake);
+    while riscv::register::mip::read().bits() & wake == 0 {
+        unsafe { riscv::asm::wfi() };
+    }
+    mie::write(saved);
+}
+
+/// 检查特权软件传来的物理地址缓冲区。
+///
+/// 缓冲区必须完整位于特权软件可访问的主存中，不能触及 SBI 区域。
+fn supervisor_buffer(len: usize, base_lo: usize, base_hi: usize) -> Option<Range<usize>> {
+    if base_hi != 0 {
+        return None;
+    }
+    let end = base_lo.checked_add(len)?;
+    let memory = unsafe { &SUPERVISOR_MEMORY };
+    if memory.start <= base_lo && end <= memory.end {
+        Some(base_lo..end)
+    } else {
+        None
+    }
+}
+
 impl rustsbi::Timer for Impl {
     fn set_timer(&self, stime_value: u64) {
-        unsafe {
-            let clint = &*hal::pac::CLINT::PTR;
-            clint.mtimecmpl.write(|w| w.bits(stime_value as _));
-            clint
-                .mtimecmph
-                .write(|w| w.bits((stime_value >> u32::BITS) as _));
-            mip::clear_stimer();
-        }
+        pmu::firmware_event(pmu::fw::SET_TIMER);
+        timer::set(0, stime_value);
     }
 }
 
 impl rustsbi::Reset for Impl {
-    fn system_reset(&self, _reset_type: u32, _reset_reason: u32) -> SbiRet {
-        print!("[rustsbi] system reset ");
-        let mut arrow = common::Arrow::init(25, |arr| {
-            print!("{}", unsafe { core::str::from_utf8_unchecked(arr) })
-        });
-        loop {
-            arrow.next();
-            for _ in 0..0x80_0000 {
-                core::hint::spin_loop();
+    fn system_reset(&self, reset_type: u32, reset_reason: u32) -> SbiRet {
+        use sbi_spec::srst::*;
+        match reset_reason {
+            RESET_REASON_NO_REASON | RESET_REASON_SYSTEM_FAILURE => {}
+            // SBI 实现或厂商定义的原因
+            0xe000_0000..=0xffff_ffff => {}
+            _ => return SbiRet::invalid_param(),
+        }
+        match reset_type {
+            RESET_TYPE_SHUTDOWN => {
+                println!("[rustsbi] system shutdown, reason = {reset_reason:#x}");
+                shutdown()
+            }
+            RESET_TYPE_COLD_REBOOT | RESET_TYPE_WARM_REBOOT => {
+                println!("[rustsbi] system reboot, reason = {reset_reason:#x}");
+                hal::wdt::reset_system()
             }
+            0xf000_0000..=0xffff_ffff => SbiRet::not_supported(),
+            _ => SbiRet::invalid_param(),
         }
     }
 }
 
+/// 板上没有可控的电源，关中断停住核心。
+fn shutdown() -> ! {
+    use crate::riscv_spec::{mie, mstatus};
+    mstatus::update(|bits| *bits &= !mstatus::MIE);
+    mie::write(0);
+    loop {
+        unsafe { riscv::asm::wfi() };
+    }
+}
+
 impl rustsbi::Ipi for Impl {
     fn send_ipi(&self, hart_mask: HartMask) -> SbiRet {
         if hart_mask.has_bit(0) {
+            pmu::firmware_event(pmu::fw::IPI_SENT);
             unsafe { (*(CLINT_BASE as *const Clint)).set_msip(0) };
         }
         SbiRet::success(0)
     }
 }
+
+impl rustsbi::Fence for Impl {
+    fn remote_fence_i(&self, hart_mask: HartMask) -> SbiRet {
+        if !hart_mask.has_bit(0) {
+            return SbiRet::invalid_param();
+        }
+        unsafe { asm!("fence.i") };
+        SbiRet::success(0)
+    }
+
+    fn remote_sfence_vma(&self, hart_mask: HartMask, start_addr: usize, size: usize) -> SbiRet {
+        if !hart_mask.has_bit(0) {
+            return SbiRet::invalid_param();
+        }
+        match sfence_range(start_addr, size) {
+            Some(Sfence::All) => unsafe { asm!("sfence.vma") },
+            Some(Sfence::Pages(range)) => {
+                for addr in range {
+                    unsafe { asm!("sfence.vma {}, zero", in(reg) addr) };
+                }
+            }
+            None => return SbiRet::invalid_address(),
+        }
+        SbiRet::success(0)
+    }
+
+    fn remote_sfence_vma_asid(
+        &self,
+        hart_mask: HartMask,
+        start_addr: usize,
+        size: usize,
+        asid: usize,
+    ) -> SbiRet {
+        if !hart_mask.has_bit(0) {
+            return SbiRet::invalid_param();
+        }
+        match sfence_range(start_addr, size) {
+            // 只刷新这个地址空间
+            Some(Sfence::All) => unsafe { asm!("sfence.vma zero, {}", in(reg) asid) },
+            Some(Sfence::Pages(range)) => {
+                for addr in range {
+                    unsafe { asm!("sfence.vma {}, {}", in(reg) addr, in(reg) asid) };
+                }
+            }
+            None => return SbiRet::invalid_address(),
+        }
+        SbiRet::success(0)
+    }
+}
+
+/// RFENCE 要刷新的地址。
+enum Sfence {
+    /// 刷新全部地址。
+    All,
+    /// 逐页刷新，每项是一页的起始地址，可能为空。
+    Pages(StepBy<Range<usize>>),
+}
+
+/// 把 RFENCE 的地址范围转换为要刷新的地址，`None` 表示范围溢出。
+///
+/// `start_addr` 和 `size` 都是 0 或 `size` 是 `usize::MAX` 时刷新全部地址，
+/// 其他 `size` 为 0 的调用什么都不刷新。
+fn sfence_range(start_addr: usize, size: usize) -> Option<Sfence> {
+    /// 逐页刷新的最大页数，更大的范围直接全部刷新。
+    const MAX_PAGES: usize = 64;
+    const PAGE_SIZE: usize = 4096;
+    if size == usize::MAX || (start_addr == 0 && size == 0) {
+        return Some(Sfence::All);
+    }
+    let end = start_addr.checked_add(size)?;
+    if size > MAX_PAGES * PAGE_SIZE {
+        return Some(Sfence::All);
+    }
+    let start = if size == 0 {
+        end
+    } else {
+        start_addr & !(PAGE_SIZE - 1)
+    };
+    Some(Sfence::Pages((start..end).step_by(PAGE_SIZE)))
+}
+
+impl rustsbi::Hsm for Impl {
+    fn hart_start(&self, hartid: usize, start_addr: usize, opaque: usize) -> SbiRet {
+        if hartid != 0 {
+            return SbiRet::invalid_param();
+        }
+        if !unsafe { SUPERVISOR_MEMORY.contains(&start_addr) } {
+            return SbiRet::invalid_address();
+        }
+        match HART.start() {
+            Ok(()) => {
+                unsafe {
+                    SUPERVISOR = Supervisor { start_addr, opaque };
+                    (*(CLINT_BASE as *const Clint)).set_msip(hartid);
+                }
+                SbiRet::success(0)
+            }
+            Err(_) => SbiRet::already_available(),
+        }
+    }
+
+    fn hart_stop(&self) -> SbiRet {
+        // 停止在返回特权软件之前完成
+        if HART.stop().is_ok() {
+            SbiRet::success(0)
+        } else {
+            SbiRet::failed()
+        }
+    }
+
+    fn hart_get_status(&self, hartid: usize) -> SbiRet {
+        if hartid == 0 {
+            SbiRet::success(HART.status())
+        } else {
+            SbiRet::invalid_param()
+        }
+    }
+
+    fn hart_suspend(&self, suspend_type: u32, resume_addr: usize, opaque: usize) -> SbiRet {
+        use sbi_spec::hsm::{HART_SUSPEND_TYPE_NON_RETENTIVE, HART_SUSPEND_TYPE_RETENTIVE};
+        let retentive = match suspend_type {
+            HART_SUSPEND_TYPE_RETENTIVE => true,
+            HART_SUSPEND_TYPE_NON_RETENTIVE => {
+                if !unsafe { SUPERVISOR_MEMORY.contains(&resume_addr) } {
+                    return SbiRet::invalid_address();
+                }
+                false
+            }
+            // 保留的和没有实现的平台相关类型
+            _ => return SbiRet::invalid_param(),
+        };
+        if HART.suspend().is_err() {
+            return SbiRet::failed();
+        }
+        // 任何 mie 中使能的中断都能唤醒硬件线程，中断留给返回特权态之后处理
+        unsafe { riscv::asm::wfi() };
+        if !retentive {
+            unsafe {
+                SUPERVISOR = Supervisor {
+                    start_addr: resume_addr,
+                    opaque,
+                }
+            };
+        }
+        let _ = HART.resume(retentive);
+        SbiRet::success(0)
+    }
+}
+
+impl rustsbi::Pmu for Impl {
+    #[inline]
+    fn num_counters(&self) -> usize {
+        pmu::NUM_COUNTERS
+    }
+
+    #[inline]
+    fn counter_get_info(&self, counter_idx: usize) -> SbiRet {
+        pmu::get_info(counter_idx)
+    }
+
+    #[inline]
+    fn counter_config_matching(
+        &self,
+        counter_idx_base: usize,
+        counter_idx_mask: usize,
+        config_flags: usize,
+        event_idx: usize,
+        _event_data: u64,
+    ) -> SbiRet {
+        pmu::config_matching(counter_idx_base, counter_idx_mask, config_flags, event_idx)
+    }
+
+    #[inline]
+    fn counter_start(
+        &self,
+        counter_idx_base: usize,
+        counter_idx_mask: usize,
+        start_flags: usize,
+        initial_value: u64,
+    ) -> SbiRet {
+        pmu::start(
+            counter_idx_base,
+            counter_idx_mask,
+            start_flags,
+            initial_value,
+        )
+    }
+
+    #[inline]
+    fn counter_stop(
+        &self,
+        counter_idx_base: usize,
+        counter_idx_mask: usize,
+        stop_flags: usize,
+    ) -> SbiRet {
+        pmu::stop(counter_idx_base, counter_idx_mask, stop_flags)
+    }
+
+    #[inline]
+    fn counter_fw_read(&self, counter_idx: usize) -> SbiRet {
+        pmu::fw_read(counter_idx)
+    }
+}
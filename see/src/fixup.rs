@@ -0,0 +1,19 @@
+//! 启动特权软件前修改设备树。
+//!
+//! 修改的各个阶段见 [`common::fixup`]。
+
+use common::{
+    fdt::{Error, Fdt},
+    memory::DTB_WINDOW,
+};
+
+pub(crate) use common::fixup::Params;
+
+/// 修改位于 `dtb` 的设备树。
+pub(crate) fn fixup(dtb: usize, params: &Params) -> Result<(), Error> {
+    let mut fdt = unsafe { Fdt::from_raw_parts(dtb, DTB_WINDOW) }?;
+    common::fixup::apply(&mut fdt, params, |name, e| {
+        println!("[rustsbi] dtb fixup stage {name} failed: {e:?}");
+    });
+    Ok(())
+}
@@ -0,0 +1,583 @@
+//! GDB 远程串行协议桩。
+//!
+//! M 态 `ebreak` 或 panic 时进入，通过 UART0 与 gdb 通信，支持读写寄存器和内存、软件断点和单步。
+//! 断点都用 `c.ebreak`，不会覆盖下一条指令；单步在所有可能的下一条指令处放置临时断点。
+//! 访存经过 `unprivileged` 的异常保护，gdb 访问不存在的地址不会使固件崩溃。
+
+use crate::{
+    riscv_spec::{mepc, mstatus},
+    unprivileged, xreg, Console,
+};
+use core::arch::asm;
+use fast_trap::FlowContext;
+use rcore_console::Console as _;
+
+const C_EBREAK: u16 = 0x9002;
+const EBREAK: u32 = 0x0010_0073;
+
+const MAX_BREAKPOINTS: usize = 16;
+const PACKET_SIZE: usize = 1024;
+
+const SIGTRAP: u8 = 5;
+const SIGABRT: u8 = 6;
+
+/// 按 gdb 的顺序排列的寄存器：`x0..=x31`、`pc`。
+type Registers = [usize; 33];
+const PC: usize = 32;
+
+#[derive(Clone, Copy)]
+struct Breakpoint {
+    addr: usize,
+    saved: u16,
+}
+
+/// gdb 要求的恢复方式。
+enum Resume {
+    Continue,
+    Step,
+    Detach,
+}
+
+struct Stub {
+    /// gdb 设置的断点。
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+    /// 单步用的临时断点。
+    temporary: [Option<Breakpoint>; 2],
+    /// 为了越过断点而暂时移除的断点。
+    step_over: Option<usize>,
+    /// 单步或越过断点后是否停下报告。
+    stepping: bool,
+    /// gdb 已连接，恢复后停下时要主动报告。
+    attached: bool,
+}
+
+static mut STUB: Stub = Stub {
+    breakpoints: [None; MAX_BREAKPOINTS],
+    temporary: [None; 2],
+    step_over: None,
+    stepping: false,
+    attached: false,
+};
+
+static mut PACKET: [u8; PACKET_SIZE] = [0; PACKET_SIZE];
+
+/// 处理 M 态的 `ebreak`，`ctx` 必须是完整路径的上下文。
+///
+/// 陷入来自 M 态，`mstatus.MPP` 就是 M 态，访存不需要额外设置。
+pub(crate) fn on_breakpoint(ctx: &mut FlowContext) {
+    let stub = unsafe { &mut STUB };
+    let mut regs = [0; 33];
+    for (i, reg) in regs[..32].iter_mut().enumerate() {
+        *reg = xreg::get(ctx, i);
+    }
+    regs[PC] = mepc::read();
+
+    let temporary = stub.clear_temporary(regs[PC]);
+    if let Some(addr) = stub.step_over.take() {
+        stub.insert(addr);
+    }
+    // 越过断点后继续执行，不需要停下
+    if !temporary || stub.stepping {
+        let resume = stub.serve(&mut regs, SIGTRAP);
+        stub.resume(&mut regs, resume);
+    }
+
+    for (i, reg) in regs[..32].iter().enumerate() {
+        xreg::set(ctx, i, *reg);
+    }
+    mepc::write(regs[PC]);
+}
+
+/// panic 后进入，只能检查现场，恢复执行时退出。
+pub(crate) fn on_panic(gprs: &[usize; 32], pc: usize) {
+    let stub = unsafe { &mut STUB };
+    let mut regs = [0; 33];
+    regs[..32].copy_from_slice(gprs);
+    regs[PC] = pc;
+    // 访存以 `mstatus.MPP` 的特权级进行，panic 时不一定是 M 态
+    mstatus::update(|bits| *bits |= mstatus::MPP_MACHINE);
+    println!("[rustsbi] waiting for gdb on uart0");
+    stub.serve(&mut regs, SIGABRT);
+    stub.resume(&mut regs, Resume::Detach);
+}
+
+impl Stub {
+    /// 处理 gdb 的请求，直到 gdb 要求恢复执行。
+    fn serve(&mut self, regs: &mut Registers, signal: u8) -> Resume {
+        if self.attached {
+            Reply::begin().byte(b'S').hex_u8(signal).end();
+        }
+        loop {
+            let packet = receive();
+            self.attached = true;
+            let (cmd, args) = (packet[0], &packet[1..]);
+            match cmd {
+                b'?' => Reply::begin().byte(b'S').hex_u8(signal).end(),
+                b'g' => {
+                    let mut reply = Reply::begin();
+                    for reg in regs.iter() {
+                        reply = reply.hex_le(*reg);
+                    }
+                    reply.end()
+                }
+                b'G' => match parse_registers(args, regs) {
+                    Some(()) => Reply::ok(),
+                    None => Reply::error(1),
+                },
+                b'p' => match parse_hex(args).filter(|i| *i < regs.len()) {
+                    Some(i) => Reply::begin().hex_le(regs[i]).end(),
+                    None => Reply::error(1),
+                },
+                b'P' => {
+                    let value = split(args, b'=').and_then(|(i, val)| {
+                        let i = parse_hex(i).filter(|i| *i < regs.len())?;
+                        Some((i, parse_hex_le(val)?))
+                    });
+                    match value {
+                        Some((0, _)) => Reply::ok(),
+                        Some((i, val)) => {
+                            regs[i] = val;
+                            Reply::ok()
+                        }
+                        None => Reply::error(1),
+                    }
+                }
+                b'm' => read_memory(args),
+                b'M' => write_memory(args),
+                b'Z' | b'z' => {
+                    let addr = args
+                        .strip_prefix(b"0,")
+                        .and_then(|args| parse_hex(split(args, b',')?.0));
+                    match addr {
+                        Some(addr) if cmd == b'Z' && self.add(addr) => Reply::ok(),
+                        Some(addr) if cmd == b'z' && self.remove(addr) => Reply::ok(),
+                        Some(_) => Reply::error(14),
+                        // 只支持软件断点
+                        None => Reply::begin().end(),
+                    }
+                }
+                b'c' | b's' => {
+                    if let Some(addr) = parse_hex(args) {
+                        regs[PC] = addr;
+                    }
+                    break if cmd == b'c' {
+                        Resume::Continue
+                    } else {
+                        Resume::Step
+                    };
+                }
+                b'D' => {
+                    Reply::ok();
+                    break Resume::Detach;
+                }
+                b'k' => break Resume::Detach,
+                b'q' if args.starts_with(b"Supported") => Reply::begin()
+                    .str(b"PacketSize=")
+                    .hex_u16(PACKET_SIZE as _)
+                    .end(),
+                b'q' if args == b"Attached" => Reply::begin().byte(b'1').end(),
+                b'q' if args == b"C" => Reply::begin().str(b"QC1").end(),
+                b'H' => Reply::ok(),
+                _ => Reply::begin().end(),
+            }
+        }
+    }
+
+    /// 按 gdb 的要求准备恢复执行。
+    fn resume(&mut self, regs: &mut Registers, resume: Resume) {
+        let stepping = match resume {
+            Resume::Continue => false,
+            Resume::Step => true,
+            Resume::Detach => {
+                for addr in self
+                    .breakpoints
+                    .map(|bp| bp.map(|bp| bp.addr))
+                    .iter()
+                    .flatten()
+                {
+                    self.remove(*addr);
+                }
+                self.attached = false;
+                return;
+            }
+        };
+        self.stepping = stepping;
+        let pc = regs[PC];
+        if self.find(pc).is_some() {
+            // 暂时移除断点，执行原来的指令
+            self.restore(pc);
+            self.step_over = Some(pc);
+        } else if let Some(len) = ebreak(pc) {
+            // 代码中原有的 ebreak 直接跳过
+            regs[PC] += len;
+            if stepping {
+                self.set_temporary([Some(regs[PC]), None]);
+            }
+            return;
+        }
+        if stepping || self.step_over.is_some() {
+            self.set_temporary(next_pc(regs));
+        }
+    }
+
+    #[inline]
+    fn find(&self, addr: usize) -> Option<usize> {
+        self.breakpoints
+            .iter()
+            .position(|bp| matches!(bp, Some(bp) if bp.addr == addr))
+    }
+
+    /// 添加断点。
+    fn add(&mut self, addr: usize) -> bool {
+        if self.find(addr).is_some() {
+            return true;
+        }
+        let Some(slot) = self.breakpoints.iter().position(Option::is_none) else {
+            return false;
+        };
+        match patch(addr) {
+            Some(saved) => {
+                self.breakpoints[slot] = Some(Breakpoint { addr, saved });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 删除断点。
+    fn remove(&mut self, addr: usize) -> bool {
+        match self.find(addr) {
+            Some(i) => {
+                self.restore(addr);
+                self.breakpoints[i] = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 恢复断点处原来的指令，断点仍然保留。
+    fn restore(&mut self, addr: usize) {
+        if let Some(bp) = self.find(addr).and_then(|i| self.breakpoints[i]) {
+            unpatch(&bp);
+        }
+    }
+
+    /// 重新插入暂时移除的断点。
+    fn insert(&mut self, addr: usize) {
+        if self.find(addr).is_some() {
+            patch(addr);
+        }
+    }
+
+    /// 设置临时断点，已有断点的位置不需要。
+    fn set_temporary(&mut self, addrs: [Option<usize>; 2]) {
+        for (slot, addr) in self.temporary.iter_mut().zip(addrs) {
+            *slot = addr
+                .filter(|addr| self.breakpoints.iter().flatten().all(|bp| bp.addr != *addr))
+                .and_then(|addr| {
+                    Some(Breakpoint {
+                        addr,
+                        saved: patch(addr)?,
+                    })
+                });
+        }
+        if let [Some(a), Some(b)] = self.temporary {
+            if a.addr == b.addr {
+                self.temporary[1] = None;
+            }
+        }
+    }
+
+    /// 清除临时断点，返回是否停在临时断点上。
+    fn clear_temporary(&mut self, pc: usize) -> bool {
+        let mut hit = false;
+        for bp in self.temporary.iter_mut().filter_map(Option::take) {
+            unpatch(&bp);
+            hit |= bp.addr == pc;
+        }
+        hit
+    }
+}
+
+/// `addr` 处是 `ebreak` 或 `c.ebreak` 时返回指令长度。
+fn ebreak(addr: usize) -> Option<usize> {
+    match unprivileged::load_insn(addr) {
+        Ok((EBREAK, 4)) => Some(4),
+        Ok((insn, 2)) if insn == C_EBREAK as u32 => Some(2),
+        _ => None,
+    }
+}
+
+/// 在 `addr` 写入 `c.ebreak`，返回原来的半字。
+fn patch(addr: usize) -> Option<u16> {
+    let saved = load_u16(addr)?;
+    store_u16(addr, C_EBREAK)?;
+    unsafe { asm!("fence.i") };
+    Some(saved)
+}
+
+fn unpatch(bp: &Breakpoint) {
+    store_u16(bp.addr, bp.saved);
+    unsafe { asm!("fence.i") };
+}
+
+fn load_u16(addr: usize) -> Option<u16> {
+    let lo = unprivileged::load_u8(addr).ok()?;
+    let hi = unprivileged::load_u8(addr + 1).ok()?;
+    Some(u16::from_le_bytes([lo, hi]))
+}
+
+fn store_u16(addr: usize, val: u16) -> Option<()> {
+    let [lo, hi] = val.to_le_bytes();
+    unprivileged::store_u8(addr, lo).ok()?;
+    unprivileged::store_u8(addr + 1, hi).ok()
+}
+
+/// 执行 `pc` 处的指令后可能到达的位置。
+fn next_pc(regs: &Registers) -> [Option<usize>; 2] {
+    let pc = regs[PC];
+    let Ok((insn, len)) = unprivileged::load_insn(pc) else {
+        return [None, None];
+    };
+    let next = pc + len;
+    let bits = |hi: u32, lo: u32| (insn >> lo) & ((1 << (hi - lo + 1)) - 1);
+    let sext = |val: u32, width: u32| ((val << (32 - width)) as i32 >> (32 - width)) as isize;
+    let target = |offset: isize| pc.wrapping_add_signed(offset);
+    if len == 4 {
+        match insn & 0x7f {
+            // jal
+            0x6f => {
+                let imm = bits(31, 31) << 20
+                    | bits(19, 12) << 12
+                    | bits(20, 20) << 11
+                    | bits(30, 21) << 1;
+                [Some(target(sext(imm, 21))), None]
+            }
+            // jalr
+            0x67 => {
+                let base = regs[bits(19, 15) as usize];
+                let imm = (insn as i32 >> 20) as isize;
+                [Some(base.wrapping_add_signed(imm) & !1), None]
+            }
+            // branch
+            0x63 => {
+                let imm =
+                    bits(31, 31) << 12 | bits(7, 7) << 11 | bits(30, 25) << 5 | bits(11, 8) << 1;
+                [Some(next), Some(target(sext(imm, 13)))]
+            }
+            _ => [Some(next), None],
+        }
+    } else {
+        match (bits(15, 13), insn & 0b11) {
+            // c.j
+            (0b101, 0b01) => {
+                let imm = bits(12, 12) << 11
+                    | bits(8, 8) << 10
+                    | bits(10, 9) << 8
+                    | bits(6, 6) << 7
+                    | bits(7, 7) << 6
+                    | bits(2, 2) << 5
+                    | bits(11, 11) << 4
+                    | bits(5, 3) << 1;
+                [Some(target(sext(imm, 12))), None]
+            }
+            // c.beqz、c.bnez
+            (0b110 | 0b111, 0b01) => {
+                let imm = bits(12, 12) << 8
+                    | bits(6, 5) << 6
+                    | bits(2, 2) << 5
+                    | bits(11, 10) << 3
+                    | bits(4, 3) << 1;
+                [Some(next), Some(target(sext(imm, 9)))]
+            }
+            // c.jr、c.jalr
+            (0b100, 0b10) if bits(6, 2) == 0 && bits(11, 7) != 0 => {
+                [Some(regs[bits(11, 7) as usize]), None]
+            }
+            _ => [Some(next), None],
+        }
+    }
+}
+
+/// `m addr,len`
+fn read_memory(args: &[u8]) {
+    let Some((addr, len)) =
+        split(args, b',').and_then(|(a, l)| Some((parse_hex(a)?, parse_hex(l)?)))
+    else {
+        return Reply::error(1);
+    };
+    let len = len.min((PACKET_SIZE - 4) / 2);
+    // 先检查能否访问，回复开始后不能再报告错误
+    if (addr..addr + len).any(|a| unprivileged::load_u8(a).is_err()) {
+        return Reply::error(14);
+    }
+    let mut reply = Reply::begin();
+    for a in addr..addr + len {
+        reply = reply.hex_u8(unprivileged::load_u8(a).unwrap());
+    }
+    reply.end()
+}
+
+/// `M addr,len:XX...`
+fn write_memory(args: &[u8]) {
+    let parsed = split(args, b':').and_then(|(head, data)| {
+        let (addr, len) = split(head, b',')?;
+        let (addr, len) = (parse_hex(addr)?, parse_hex(len)?);
+        (data.len() == len * 2).then_some((addr, data))
+    });
+    let Some((addr, data)) = parsed else {
+        return Reply::error(1);
+    };
+    for (i, pair) in data.chunks(2).enumerate() {
+        let Some(byte) = parse_hex(pair) else {
+            return Reply::error(1);
+        };
+        if unprivileged::store_u8(addr + i, byte as _).is_err() {
+            return Reply::error(14);
+        }
+    }
+    // 可能改写了代码
+    unsafe { asm!("fence.i") };
+    Reply::ok()
+}
+
+/// `G XX...`，每个寄存器是小端序的 16 个十六进制数字。
+fn parse_registers(args: &[u8], regs: &mut Registers) -> Option<()> {
+    if args.len() < regs.len() * 16 {
+        return None;
+    }
+    for (i, hex) in args.chunks(16).take(regs.len()).enumerate().skip(1) {
+        regs[i] = parse_hex_le(hex)?;
+    }
+    Some(())
+}
+
+/// 接收一个校验正确的非空数据包。
+fn receive() -> &'static [u8] {
+    let buf = unsafe { &mut PACKET };
+    loop {
+        while get_char() != b'$' {}
+        let mut len = 0;
+        let mut sum = 0u8;
+        let mut overflow = false;
+        loop {
+            match get_char() {
+                b'#' => break,
+                c => {
+                    sum = sum.wrapping_add(c);
+                    if len < buf.len() {
+                        buf[len] = c;
+                        len += 1;
+                    } else {
+                        overflow = true;
+                    }
+                }
+            }
+        }
+        let checksum = parse_hex(&[get_char(), get_char()]);
+        if checksum == Some(sum as usize) && !overflow && len > 0 {
+            Console.put_char(b'+');
+            return &buf[..len];
+        }
+        Console.put_char(b'-');
+    }
+}
+
+#[inline]
+fn get_char() -> u8 {
+    loop {
+        if let Some(c) = Console.get_char() {
+            return c;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// 边发送边计算校验和的回复。
+///
+/// 只等待确认，不重传。
+#[must_use]
+struct Reply(u8);
+
+impl Reply {
+    #[inline]
+    fn begin() -> Self {
+        Console.put_char(b'$');
+        Self(0)
+    }
+
+    #[inline]
+    fn ok() {
+        Self::begin().str(b"OK").end()
+    }
+
+    #[inline]
+    fn error(code: u8) {
+        Self::begin().byte(b'E').hex_u8(code).end()
+    }
+
+    #[inline]
+    fn byte(self, c: u8) -> Self {
+        Console.put_char(c);
+        Self(self.0.wrapping_add(c))
+    }
+
+    fn str(self, s: &[u8]) -> Self {
+        s.iter().fold(self, |reply, c| reply.byte(*c))
+    }
+
+    fn hex_u8(self, val: u8) -> Self {
+        const HEX: &[u8; 16] = b"0123456789abcdef";
+        self.byte(HEX[(val >> 4) as usize])
+            .byte(HEX[(val & 0xf) as usize])
+    }
+
+    fn hex_u16(self, val: u16) -> Self {
+        let [hi, lo] = val.to_be_bytes();
+        self.hex_u8(hi).hex_u8(lo)
+    }
+
+    /// 按目标的字节序，即小端序，编码寄存器。
+    fn hex_le(self, val: usize) -> Self {
+        val.to_le_bytes()
+            .iter()
+            .fold(self, |reply, b| reply.hex_u8(*b))
+    }
+
+    fn end(self) {
+        let sum = self.0;
+        Console.put_char(b'#');
+        let _ = Self(0).hex_u8(sum);
+        // 等待确认
+        while !matches!(get_char(), b'+' | b'-') {}
+    }
+}
+
+#[inline]
+fn split(s: &[u8], sep: u8) -> Option<(&[u8], &[u8])> {
+    let i = s.iter().position(|c| *c == sep)?;
+    Some((&s[..i], &s[i + 1..]))
+}
+
+fn parse_hex(s: &[u8]) -> Option<usize> {
+    if s.is_empty() || s.len() > 16 {
+        return None;
+    }
+    s.iter().try_fold(0usize, |acc, c| {
+        let digit = (*c as char).to_digit(16)?;
+        Some(acc << 4 | digit as usize)
+    })
+}
+
+/// 解析小端序编码的寄存器。
+fn parse_hex_le(s: &[u8]) -> Option<usize> {
+    if s.len() != 16 {
+        return None;
+    }
+    let mut bytes = [0u8; 8];
+    for (byte, pair) in bytes.iter_mut().zip(s.chunks(2)) {
+        *byte = parse_hex(pair)? as _;
+    }
+    Some(usize::from_le_bytes(bytes))
+}
@@ -0,0 +1,10 @@
+//! 硬件线程状态管理。
+//!
+//! 状态机在 `common` 中实现，可以在主机上测试。
+
+use common::hsm::{HartState, START_PENDING};
+
+/// 启动硬件线程的状态。
+///
+/// 进入 SEE 时硬件线程正在等待启动特权软件。
+pub(crate) static HART: HartState = HartState::new(START_PENDING);
@@ -0,0 +1,166 @@
+//! 识别内核格式并放到要求的位置。
+//!
+//! RISC-V Linux `Image` 开头是 64 字节的头，要求放在主存起点之后 `text_offset` 处，
+//! 加上未初始化数据共占用 `image_size` 字节。
+//! ELF 文件按程序头把可加载段放到物理地址。其他格式视作从头开始执行的二进制。
+
+use common::elf::{self, Elf};
+use core::{fmt, ops::Range};
+
+/// `Image` 头的魔数 "RSC\x05"。
+const MAGIC2: u32 = u32::from_le_bytes(*b"RSC\x05");
+
+/// `Image` 头。
+#[repr(C)]
+struct Header {
+    _code0: u32,
+    _code1: u32,
+    text_offset: u64,
+    image_size: u64,
+    _flags: u64,
+    version: u32,
+    _res1: u32,
+    _res2: u64,
+    _magic: u64,
+    magic2: u32,
+    _res3: u32,
+}
+
+/// 内核放不下。
+#[derive(Debug)]
+pub(crate) enum Error {
+    /// 要求的位置与固件重叠。
+    OverlapFirmware { start: usize, firmware_end: usize },
+    /// 内核超出可用的主存。
+    TooLarge { range: Range<usize>, limit: usize },
+    /// 需要搬移，但头中没有大小。
+    UnknownSize,
+    /// ELF 文件无法解析。
+    Elf(elf::Error),
+    /// ELF 段超出特权软件可用的主存。
+    OutOfRange {
+        segment: Range<usize>,
+        available: Range<usize>,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OverlapFirmware {
+                start,
+                firmware_end,
+            } => write!(
+                f,
+                "kernel requires to start at {start:#x}, overlapping firmware below {firmware_end:#x}"
+            ),
+            Self::TooLarge { range, limit } => write!(
+                f,
+                "kernel occupies {range:#x?}, exceeding available memory below {limit:#x}"
+            ),
+            Self::UnknownSize => write!(f, "kernel needs relocation but image_size is 0"),
+            Self::Elf(e) => write!(f, "invalid ELF kernel: {e:?}"),
+            Self::OutOfRange { segment, available } => write!(
+                f,
+                "ELF segment {segment:#x?} is out of available memory {available:#x?}"
+            ),
+        }
+    }
+}
+
+/// 准备启动位于 `load` 的内核，返回入口地址。
+///
+/// 内核只能使用 `load..limit` 的主存。
+pub(crate) fn prepare(load: usize, mem: &Range<usize>, limit: usize) -> Result<usize, Error> {
+    let buf = unsafe { core::slice::from_raw_parts(load as *const u8, limit - load) };
+    match Elf::parse(buf) {
+        Ok(elf) => return load_elf(elf, load..limit),
+        Err(elf::Error::BadMagic) => {}
+        Err(e) => return Err(Error::Elf(e)),
+    }
+    let header = unsafe { &*(load as *const Header) };
+    if header.magic2 != MAGIC2 {
+        return Ok(load);
+    }
+    let start = mem.start + header.text_offset as usize;
+    let size = header.image_size as usize;
+    println!(
+        "[rustsbi] Linux Image v{}.{}, text_offset = {:#x}, image_size = {size:#x}",
+        header.version >> 16,
+        header.version & 0xffff,
+        header.text_offset,
+    );
+    if start < load {
+        return Err(Error::OverlapFirmware {
+            start,
+            firmware_end: load,
+        });
+    }
+    if size == 0 {
+        return if start == load {
+            Ok(load)
+        } else {
+            Err(Error::UnknownSize)
+        };
+    }
+    let range = start..start + size;
+    if range.end > limit {
+        return Err(Error::TooLarge { range, limit });
+    }
+    if start != load {
+        println!("[rustsbi] relocate kernel from {load:#x} to {start:#x}");
+        unsafe { core::ptr::copy(load as *const u8, start as *mut u8, size) };
+    }
+    Ok(start)
+}
+
+/// 把 ELF 的可加载段放到物理地址，清零未初始化部分，返回入口地址。
+///
+/// 如果段与文件本身重叠，先把文件搬到可用主存的末尾。
+fn load_elf(elf: Elf, available: Range<usize>) -> Result<usize, Error> {
+    const PAGE: usize = 4096;
+    for segment in elf.segments() {
+        let segment = segment.memory();
+        if segment.start < available.start || segment.end > available.end {
+            return Err(Error::OutOfRange { segment, available });
+        }
+    }
+    let Some(span) = elf.memory() else {
+        return Ok(elf.entry());
+    };
+    let file = available.start..available.start + elf.file_size();
+    if file.end > available.end {
+        return Err(Error::Elf(elf::Error::Truncated));
+    }
+    let file = if span.start < file.end && file.start < span.end {
+        let staging = (available.end - file.len()) & !(PAGE - 1);
+        if staging < span.end {
+            return Err(Error::OutOfRange {
+                segment: span,
+                available: available.start..staging,
+            });
+        }
+        println!("[rustsbi] stage ELF kernel at {staging:#x}");
+        unsafe { core::ptr::copy(file.start as *const u8, staging as *mut u8, file.len()) };
+        staging..staging + file.len()
+    } else {
+        file
+    };
+    let buf = unsafe { core::slice::from_raw_parts(file.start as *const u8, file.len()) };
+    let elf = Elf::parse(buf).map_err(Error::Elf)?;
+    for segment in elf.segments() {
+        println!(
+            "[rustsbi] load ELF segment {:#x?} to {:#x}",
+            segment.file, segment.paddr
+        );
+        let filesz = segment.file.len();
+        unsafe {
+            let dst = segment.paddr as *mut u8;
+            core::ptr::copy(buf[segment.file].as_ptr(), dst, filesz);
+            dst.add(filesz)
+                .write_bytes(0, segment.memsz.saturating_sub(filesz));
+        }
+    }
+    unsafe { core::arch::asm!("fence.i") };
+    Ok(elf.entry())
+}
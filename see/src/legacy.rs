@@ -0,0 +1,97 @@
+//! SBI v0.1 遗留扩展。
+//!
+//! 遗留扩展只用 `a0` 返回，其他寄存器保持不变。
+//! 控制台和软件中断之外的调用转换为对应的新扩展，由 RustSBI 处理。
+
+use crate::{extensions::sbi, unprivileged, Console};
+use rcore_console::Console as _;
+use rustsbi::spec::binary::SbiRet;
+use sbi_spec::{legacy::*, rfnc, spi, srst, time};
+
+/// 是否是遗留扩展。
+#[inline]
+pub(crate) fn probe(extension: usize) -> bool {
+    (LEGACY_SET_TIMER..=LEGACY_SHUTDOWN).contains(&extension)
+}
+
+/// 处理遗留扩展调用，返回要写入 `a0` 的值，不是遗留扩展时返回 `None`。
+///
+/// 除 `getchar` 外，`a0` 都是错误码。
+pub(crate) fn handle_ecall(extension: usize, [a0, a1, a2, a3, ..]: [usize; 6]) -> Option<usize> {
+    let ret = match extension {
+        LEGACY_SET_TIMER => {
+            sbi().handle_ecall(time::EID_TIME, time::SET_TIMER, [a0, 0, 0, 0, 0, 0])
+        }
+        LEGACY_CONSOLE_PUTCHAR => {
+            Console.put_char(a0 as u8);
+            SbiRet::success(0)
+        }
+        // 没有输入时返回 -1
+        LEGACY_CONSOLE_GETCHAR => {
+            return Some(Console.get_char().map_or(usize::MAX, |c| c as usize));
+        }
+        LEGACY_CLEAR_IPI => {
+            unsafe { riscv::register::mip::clear_ssoft() };
+            SbiRet::success(0)
+        }
+        LEGACY_SEND_IPI => match hart_mask(a0) {
+            Ok((mask, base)) => {
+                sbi().handle_ecall(spi::EID_SPI, spi::SEND_IPI, [mask, base, 0, 0, 0, 0])
+            }
+            Err(ret) => ret,
+        },
+        LEGACY_REMOTE_FENCE_I => match hart_mask(a0) {
+            Ok((mask, base)) => sbi().handle_ecall(
+                rfnc::EID_RFNC,
+                rfnc::REMOTE_FENCE_I,
+                [mask, base, 0, 0, 0, 0],
+            ),
+            Err(ret) => ret,
+        },
+        LEGACY_REMOTE_SFENCE_VMA => match hart_mask(a0) {
+            Ok((mask, base)) => sbi().handle_ecall(
+                rfnc::EID_RFNC,
+                rfnc::REMOTE_SFENCE_VMA,
+                [mask, base, a1, a2, 0, 0],
+            ),
+            Err(ret) => ret,
+        },
+        LEGACY_REMOTE_SFENCE_VMA_ASID => match hart_mask(a0) {
+            Ok((mask, base)) => sbi().handle_ecall(
+                rfnc::EID_RFNC,
+                rfnc::REMOTE_SFENCE_VMA_ASID,
+                [mask, base, a1, a2, a3, 0],
+            ),
+            Err(ret) => ret,
+        },
+        LEGACY_SHUTDOWN => sbi().handle_ecall(
+            srst::EID_SRST,
+            srst::SYSTEM_RESET,
+            [
+                srst::RESET_TYPE_SHUTDOWN as _,
+                srst::RESET_REASON_NO_REASON as _,
+                0,
+                0,
+                0,
+                0,
+            ],
+        ),
+        _ => return None,
+    };
+    Some(ret.error)
+}
+
+/// 读特权软件传来的核位图，返回新扩展的 `(hart_mask, hart_mask_base)`。
+///
+/// 位图地址是特权软件的虚地址，空指针表示所有核。
+fn hart_mask(addr: usize) -> Result<(usize, usize), SbiRet> {
+    if addr == 0 {
+        return Ok((0, usize::MAX));
+    }
+    let mut mask = 0;
+    for i in 0..core::mem::size_of::<usize>() {
+        let byte = unprivileged::load_u8(addr + i).map_err(|_| SbiRet::invalid_address())?;
+        mask |= (byte as usize) << (i * 8);
+    }
+    Ok((mask, 0))
+}
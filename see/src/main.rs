@@ -2,11 +2,31 @@
 #![no_main]
 #![feature(naked_functions, asm_const)]
 
+mod cache;
+mod cppc;
+mod crash;
+mod csr;
 mod extensions;
+mod fixup;
+#[cfg(feature = "gdb-stub")]
+mod gdb;
 mod hart_csr_utils;
+mod hsm;
+mod image;
+mod legacy;
+mod misaligned;
+mod pmp;
+mod pmu;
+mod policy;
+mod redirect;
 mod riscv_spec;
+mod shell;
+mod timer;
 mod trap_stack;
 mod trap_vec;
+mod unprivileged;
+mod unwind;
+mod xreg;
 
 #[macro_use]
 extern crate rcore_console;
@@ -14,9 +34,11 @@ extern crate rcore_console;
 use common::memory;
 use core::{arch::asm, ops::Range, panic::PanicInfo};
 use fast_trap::{EntireContext, EntireResult, FastContext, FastResult, FlowContext};
-use hal::pac::UART0;
+use hal::{
+    pac::{Peripherals, UART0},
+    uart::Uart,
+};
 use riscv_spec::*;
-use rustsbi::spec::binary::SbiRet;
 use trap_stack::Stack;
 
 const STACK_SIZE: usize = 4096;
@@ -75,10 +97,25 @@ extern "C" fn rust_main() {
             ptr = ptr.offset(1);
         }
     };
+    // 重新配置 UART0，清空 FIFO
+    {
+        use hal::{ccu::Clocks, time::U32Ext};
+        Uart::new(
+            unsafe { Peripherals::steal() }.UART0,
+            115200.bps(),
+            &Clocks::read(),
+        );
+    }
     rcore_console::init_console(&Console);
     rcore_console::set_log_level(option_env!("LOG"));
+    crash::report();
 
     let meta = Meta::static_ref();
+    policy::apply(meta.policy());
+    // 内核之前是 SBI 区域，没有内核时也可能从命令行启动
+    let kernel = meta.kernel().unwrap_or(memory::KERNEL);
+    // 启动特权软件前修改设备树
+    fixup_dtb(meta, kernel);
     let board_info = match meta.dtb() {
         Some(dtb) => parse_board_info(dtb),
         None => {
@@ -87,13 +124,12 @@ extern "C" fn rust_main() {
         }
     };
 
-    let kernel = meta.kernel().unwrap_or(0);
     print!(
         "\
 [rustsbi] RustSBI version {ver_sbi}, adapting to RISC-V SBI v1.0.0
 {logo}
 [rustsbi] Implementation     : RustSBI-D1 Version {ver_impl}
-[rustsbi] Extensions         : [legacy console, timer, reset, ipi]
+[rustsbi] Extensions         : [legacy, timer, reset, ipi, rfence, hsm, pmu, dbcn, susp, cppc, thead cache]
 [rustsbi] Platform Name      : {model}
 [rustsbi] Platform SMP       : 1
 [rustsbi] Platform Memory    : {mem:#x?}
@@ -101,6 +137,7 @@ extern "C" fn rust_main() {
 [rustsbi] Device Tree Region : {dtb:#x?}
 [rustsbi] Firmware Address   : {firmware:#x}
 [rustsbi] Supervisor Address : {kernel:#x}
+[rustsbi] Boot Policy        : {policy}
 ",
         model = board_info.as_ref().map_or("unknown", |i| i.model.as_str()),
         mem = board_info.as_ref().map_or(0..0, |i| i.mem.clone()),
@@ -109,37 +146,59 @@ extern "C" fn rust_main() {
         logo = rustsbi::LOGO,
         ver_impl = env!("CARGO_PKG_VERSION"),
         firmware = _start as usize,
+        kernel = meta.kernel().unwrap_or(0),
+        policy = meta.policy().name(),
     );
 
-    if kernel == 0 {
-        arrow_walk()
-    } else {
-        const DEFAULT: Range<usize> = memory::DRAM..memory::DRAM + (512 << 20);
-        let mem = board_info.as_ref().map_or(DEFAULT, |i| i.mem.clone());
-        set_pmp(mem, kernel);
-        hart_csr_utils::print_pmps();
-
-        hal::plic::allow_supervisor();
-
-        let dtb = board_info.as_ref().map_or(0, |i| i.dtb.start);
-        println!("execute_supervisor at {kernel:#x} with a1 = {dtb:#x}");
-
-        extensions::init();
-        // 准备启动调度
-        unsafe {
-            use riscv::register::medeleg;
-            asm!("csrw mcause,  {}", in(reg) cause::BOOT);
-            asm!("csrw mideleg, {}", in(reg) !0);
-            asm!("csrw medeleg, {}", in(reg) !0);
-            medeleg::clear_supervisor_env_call();
-            medeleg::clear_illegal_instruction();
-            trap_vec::load(true);
-            ROOT_STACK.prepare_for_trap();
-            SUPERVISOR = Supervisor {
-                start_addr: kernel,
-                opaque: dtb,
-            };
+    const DEFAULT: Range<usize> = memory::DRAM..memory::DRAM + (512 << 20);
+    let mem = board_info.as_ref().map_or(DEFAULT, |i| i.mem.clone());
+    // 内核不能覆盖设备树和初始内存盘
+    let limit = [
+        board_info.as_ref().map(|i| i.dtb.start),
+        meta.initrd().map(|r| r.start),
+    ]
+    .into_iter()
+    .flatten()
+    .fold(mem.end, usize::min);
+    // 倒计时被打断或没有内核时进入命令行
+    let load = shell::countdown(board_info.as_ref(), meta.kernel(), memory::KERNEL..limit);
+    // 启动目标是固件和特权软件的分界，命令行改变了目标时重新保留固件占用的主存
+    if load != kernel {
+        fixup_dtb(meta, load);
+    }
+    let entry = match image::prepare(load, &mem, limit) {
+        Ok(entry) => entry,
+        Err(e) => {
+            println!("[rustsbi] cannot boot kernel: {e}");
+            arrow_walk()
         }
+    };
+    set_pmp(mem.clone(), load, board_info.as_ref().map(|i| i.dtb.start));
+    hart_csr_utils::print_pmps();
+
+    hal::plic::allow_supervisor();
+
+    let dtb = board_info.as_ref().map_or(0, |i| i.dtb.start);
+    println!("execute_supervisor at {entry:#x} with a1 = {dtb:#x}");
+
+    extensions::init(mem, load);
+    pmu::init();
+    // 准备启动调度
+    unsafe {
+        use riscv::register::medeleg;
+        asm!("csrw mcause,  {}", in(reg) cause::BOOT);
+        asm!("csrw mideleg, {}", in(reg) !0);
+        asm!("csrw medeleg, {}", in(reg) !0);
+        medeleg::clear_supervisor_env_call();
+        medeleg::clear_illegal_instruction();
+        medeleg::clear_load_misaligned();
+        medeleg::clear_store_misaligned();
+        trap_vec::load(true);
+        ROOT_STACK.prepare_for_trap();
+        SUPERVISOR = Supervisor {
+            start_addr: entry,
+            opaque: dtb,
+        };
     }
 }
 
@@ -160,7 +219,7 @@ extern "C" fn fast_handler(
 ) -> FastResult {
     use riscv::register::{
         mcause::{self, Exception as E, Interrupt as I, Trap as T},
-        mtval, time,
+        mtval,
     };
 
     let cause = mcause::read();
@@ -168,120 +227,217 @@ extern "C" fn fast_handler(
     if (cause.cause() == T::Exception(E::Unknown) && cause.bits() == cause::BOOT)
         || cause.cause() == T::Interrupt(I::MachineSoft)
     {
-        mstatus::update(|bits| {
-            *bits &= !mstatus::MPP;
-            *bits |= mstatus::MPIE | mstatus::MPP_SUPERVISOR;
-        });
-        mie::write(mie::MSIE | mie::MTIE);
-        ctx.regs().a[0] = 0;
-        ctx.regs().a[1] = unsafe { SUPERVISOR.opaque };
-        ctx.regs().pc = unsafe { SUPERVISOR.start_addr };
-        return ctx.call(2);
+        return boot(ctx);
     }
     match cause.cause() {
         // SBI call
         T::Exception(E::SupervisorEnvCall) => {
-            use sbi_spec::{base, legacy};
-            let mut ret = extensions::sbi().handle_ecall(a7, a6, [ctx.a0(), a1, a2, a3, a4, a5]);
-            if ret.is_ok() {
-                if a7 == base::EID_BASE
-                    && a6 == base::PROBE_EXTENSION
-                    && ctx.a0() == legacy::LEGACY_CONSOLE_PUTCHAR
-                {
-                    ret.value = 1;
-                }
-            } else {
-                if a7 == legacy::LEGACY_CONSOLE_PUTCHAR {
-                    print!("{}", ctx.a0() as u8 as char);
-                    ret = SbiRet::success(a1);
+            pmu::firmware_event(pmu::fw::PLATFORM);
+            let param = [ctx.a0(), a1, a2, a3, a4, a5];
+            // 遗留扩展只用 `a0` 返回
+            let (a0, a1) = match legacy::handle_ecall(a7, param) {
+                Some(value) => (value, a1),
+                None => {
+                    let ret = extensions::handle_ecall(a7, a6, param);
+                    (ret.error, ret.value)
                 }
+            };
+            // 停止或不保持上下文的挂起，不再返回调用者
+            match hsm::HART.status() {
+                sbi_spec::hsm::HART_STATE_STOP_PENDING => return park(ctx),
+                sbi_spec::hsm::HART_STATE_RESUME_PENDING => return boot(ctx),
+                _ => {}
             }
-            ctx.regs().a = [ret.error, ret.value, a2, a3, a4, a5, a6, a7];
+            ctx.regs().a = [a0, a1, a2, a3, a4, a5, a6, a7];
             mepc::next();
             ctx.restore()
         }
-        // rdtime?
+        // 模拟 CSR
         T::Exception(E::IllegalInstruction) => {
             let ins = mtval::read();
-            const RD_MASK: usize = ((1 << 5) - 1) << 7;
-            if ins & !RD_MASK == 0xC0102073 {
-                // rdtime is actually a csrrw instruction
-
-                ctx.regs().a = [ctx.a0(), a1, a2, a3, a4, a5, a6, a7];
-                let rd = (ins & RD_MASK) >> RD_MASK.trailing_zeros();
-                match rd {
-                    0 => {}
-                    1 => ctx.regs().ra = time::read(),
-                    2 => ctx.regs().sp = time::read(),
-                    3 => ctx.regs().gp = time::read(),
-                    4 => ctx.regs().tp = time::read(),
-                    5..=7 => ctx.regs().t[rd - 5] = time::read(),
-                    8..=9 => return ctx.continue_with(entire_handler, rd - 8),
-                    10..=17 => ctx.regs().a[rd - 10] = time::read(),
-                    18..=27 => return ctx.continue_with(entire_handler, rd - 18 + 2),
-                    28..=31 => ctx.regs().t[rd - 28 + 3] = time::read(),
-                    _ => panic!("invalid rd: x{rd}"),
+            ctx.regs().a = [ctx.a0(), a1, a2, a3, a4, a5, a6, a7];
+            match csr::emulate(ins) {
+                Some((rd, _)) if xreg::lazy(rd) => ctx.continue_with(entire_handler, ins),
+                Some((rd, val)) => {
+                    pmu::firmware_event(pmu::fw::ILLEGAL_INSN);
+                    xreg::set(ctx.regs(), rd, val);
+                    mepc::next();
+                    ctx.restore()
                 }
-                mepc::next();
-                ctx.restore()
-            } else {
-                println!("IllegalInstruction: {ins:#x} at mepc = {:#x}", mepc::read(),);
-                panic!("stopped with unsupported trap")
+                // 不认识的指令交给特权软件
+                None if mstatus::read() & mstatus::MPP != mstatus::MPP_MACHINE => {
+                    redirect::to_supervisor(cause.bits(), ins);
+                    ctx.restore()
+                }
+                // 固件自身的非法指令
+                None => ctx.continue_with(crash_handler, ins),
+            }
+        }
+        // 特权软件的不对齐访存，固件自身的不对齐访存按固件错误处理
+        T::Exception(E::LoadMisaligned) | T::Exception(E::StoreMisaligned)
+            if mstatus::read() & mstatus::MPP != mstatus::MPP_MACHINE =>
+        {
+            ctx.regs().a = [ctx.a0(), a1, a2, a3, a4, a5, a6, a7];
+            ctx.continue_with(misaligned::handler, mtval::read())
+        }
+        // 特权软件引发的其他异常交给特权软件
+        T::Exception(_) if mstatus::read() & mstatus::MPP != mstatus::MPP_MACHINE => {
+            ctx.regs().a = [ctx.a0(), a1, a2, a3, a4, a5, a6, a7];
+            redirect::to_supervisor(cause.bits(), mtval::read());
+            ctx.restore()
+        }
+        // 固件中的断点
+        #[cfg(feature = "gdb-stub")]
+        T::Exception(E::Breakpoint) => {
+            ctx.regs().a = [ctx.a0(), a1, a2, a3, a4, a5, a6, a7];
+            ctx.continue_with(gdb_handler, 0)
+        }
+        // 其他陷入是固件自身的错误，转到完整路径记录现场
+        _ => {
+            ctx.regs().a = [ctx.a0(), a1, a2, a3, a4, a5, a6, a7];
+            ctx.continue_with(crash_handler, mtval::read())
+        }
+    }
+}
+
+/// 按 `SUPERVISOR` 记录的位置进入特权软件。
+///
+/// 特权软件启动时关闭地址转换和中断，a0 = hartid，a1 = opaque。
+fn boot(mut ctx: FastContext) -> FastResult {
+    let _ = hsm::HART.started();
+    mstatus::update(|bits| {
+        *bits &= !(mstatus::MPP | mstatus::SIE);
+        *bits |= mstatus::MPIE | mstatus::MPP_SUPERVISOR;
+    });
+    mie::write(mie::MSIE | mie::MTIE);
+    unsafe { asm!("csrw satp, zero") };
+    ctx.regs().a[0] = 0;
+    ctx.regs().a[1] = unsafe { SUPERVISOR.opaque };
+    ctx.regs().pc = unsafe { SUPERVISOR.start_addr };
+    ctx.call(2)
+}
+
+/// 停住已停止的硬件线程，直到 `hart_start` 再次启动它。
+fn park(ctx: FastContext) -> FastResult {
+    use riscv::register::mip;
+
+    let _ = hsm::HART.stopped();
+    mie::write(mie::MSIE);
+    loop {
+        unsafe { riscv::asm::wfi() };
+        if mip::read().msoft() {
+            unsafe { (*(hal::CLINT_BASE as *const aclint::SifiveClint)).clear_msip(0) };
+            if hsm::HART.status() == sbi_spec::hsm::HART_STATE_START_PENDING {
+                break boot(ctx);
             }
         }
-        // 其他陷入
-        trap => {
-            println!(
-                "
+    }
+}
+
+/// 模拟目标是 `s` 寄存器的 CSR 指令，参数是指令。
+#[inline(never)]
+extern "C" fn entire_handler(ctx: EntireContext<usize>) -> EntireResult {
+    let (mut ctx, ins) = ctx.split();
+    if let Some((rd, val)) = csr::emulate(ins.get()) {
+        pmu::firmware_event(pmu::fw::ILLEGAL_INSN);
+        xreg::set(ctx.regs(), rd, val);
+    }
+    mepc::next();
+    ctx.restore()
+}
+
+/// 记录固件自身的陷入并停止，参数是 `mtval`。
+#[inline(never)]
+extern "C" fn crash_handler(ctx: EntireContext<usize>) -> EntireResult {
+    use riscv::register::mcause;
+
+    let (mut ctx, mtval) = ctx.split();
+    let cause = mcause::read();
+    crash::trap(ctx.regs(), cause.bits(), mtval.get());
+    println!(
+        "
 -----------------------------
-> trap:    {trap:?}
+> trap:    {:?}
 > mstatus: {:#018x}
 > mepc:    {:#018x}
 > mtval:   {:#018x}
 -----------------------------
             ",
-                mstatus::read(),
-                mepc::read(),
-                mtval::read()
-            );
-            panic!("stopped with unsupported trap")
-        }
-    }
+        cause.cause(),
+        mstatus::read(),
+        mepc::read(),
+        mtval.get()
+    );
+    panic!("stopped with unsupported trap")
 }
 
+/// 在完整路径上进入调试桩。
+#[cfg(feature = "gdb-stub")]
 #[inline(never)]
-extern "C" fn entire_handler(ctx: EntireContext<usize>) -> EntireResult {
-    let (mut ctx, rd) = ctx.split();
-    ctx.regs().s[rd.get()] = riscv::register::time::read();
-
-    mepc::next();
+extern "C" fn gdb_handler(ctx: EntireContext<usize>) -> EntireResult {
+    let (mut ctx, _) = ctx.split();
+    gdb::on_breakpoint(ctx.regs());
     ctx.restore()
 }
 
-/// 设置 PMP。
-fn set_pmp(mem: core::ops::Range<usize>, kernel: usize) {
-    use riscv::register::*;
-    unsafe {
-        pmpcfg0::set_pmp(0, Range::OFF, Permission::NONE, false);
-        pmpaddr0::write(0);
-        // 外设
-        pmpcfg0::set_pmp(1, Range::TOR, Permission::RW, false);
-        pmpaddr1::write(mem.start >> 2);
-        // SBI
-        pmpcfg0::set_pmp(2, Range::TOR, Permission::NONE, false);
-        pmpaddr2::write(kernel >> 2);
-        // 主存
-        pmpcfg0::set_pmp(3, Range::TOR, Permission::RWX, false);
-        pmpaddr3::write(mem.end >> 2);
-        // 其他
-        pmpcfg0::set_pmp(4, Range::TOR, Permission::RW, false);
-        pmpaddr4::write(1 << (usize::BITS - 1));
+/// 修改设备树，`DRAM..firmware_end` 是固件占用的主存。
+fn fixup_dtb(meta: &memory::Meta, firmware_end: usize) {
+    let Some(dtb) = meta.dtb() else {
+        return;
+    };
+    let params = fixup::Params {
+        firmware: memory::DRAM..firmware_end,
+        memory: meta
+            .dram_size()
+            .map(|size| memory::DRAM..memory::DRAM + size),
+        timebase: hal::ccu::HOSC.0,
+        bootargs: meta
+            .bootargs()
+            .map(|addr| unsafe { core::ffi::CStr::from_ptr(addr as _) }),
+        initrd: meta.initrd(),
+    };
+    if let Err(e) = fixup::fixup(dtb, &params) {
+        println!("[rustsbi] invalid dtb, fixup skipped: {e:?}");
     }
 }
 
+/// 按设备树规划 PMP，没有设备树或条目不够时用固定的布局。
+///
+/// `mem.start..kernel` 是 SBI 区域，特权软件不能访问。
+fn set_pmp(mem: Range<usize>, kernel: usize, dtb: Option<usize>) {
+    use common::pmp::{Regions, R, W, X};
+    static mut REGIONS: Regions = Regions::new();
+
+    let regions = unsafe { &mut REGIONS };
+    let plan = dtb
+        .filter(|dtb| regions.collect_dtb(*dtb) && regions.push(mem.start..kernel, 0))
+        .and_then(|_| regions.plan())
+        .unwrap_or_else(|| {
+            println!("[rustsbi] fallback to fixed pmp layout");
+            regions.clear();
+            // 外设
+            regions.push(0..mem.start, R | W);
+            // 主存
+            regions.push(mem.clone(), R | W | X);
+            // 其他
+            regions.push(mem.end..1 << (usize::BITS - 1), R | W);
+            // SBI
+            regions.push(mem.start..kernel, 0);
+            regions.plan().unwrap()
+        });
+    pmp::program(&plan);
+}
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    crash::panic(info);
     println!("{info}");
+    unwind::print(crash::frames());
+    #[cfg(feature = "gdb-stub")]
+    {
+        let (regs, pc) = crash::context();
+        gdb::on_panic(&regs, pc);
+    }
     arrow_walk()
 }
 
@@ -309,15 +465,24 @@ struct Console;
 impl rcore_console::Console for Console {
     #[inline]
     fn put_char(&self, c: u8) {
-        let uart = unsafe { &*UART0::ptr() };
-        // 等待 FIFO 空位
-        while uart.usr.read().tfnf().is_full() {
-            core::hint::spin_loop();
-        }
-        uart.thr().write(|w| w.thr().variant(c));
+        uart().write_byte(c);
+    }
+}
+
+impl Console {
+    /// 从接收 FIFO 取一个字符，FIFO 为空时返回 `None`。
+    #[inline]
+    fn get_char(&self) -> Option<u8> {
+        uart().read().ok()
     }
 }
 
+/// `rust_main` 开头配置的 UART0。
+#[inline]
+fn uart() -> Uart<UART0> {
+    Uart::attach(unsafe { Peripherals::steal() }.UART0)
+}
+
 /// 从设备树采集的板信息。
 struct BoardInfo {
     pub dtb: Range<usize>,
@@ -334,6 +499,18 @@ impl<const N: usize> StringInline<N> {
     }
 }
 
+impl<const N: usize> core::fmt::Write for StringInline<N> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let end = self.0 + s.len();
+        if end > N {
+            return Err(core::fmt::Error);
+        }
+        self.1[self.0..end].copy_from_slice(s.as_bytes());
+        self.0 = end;
+        Ok(())
+    }
+}
+
 fn parse_board_info(addr: usize) -> Option<BoardInfo> {
     use common::dtb_walker::{Dtb, DtbObj, HeaderError::*, Property, WalkOperation::*};
 
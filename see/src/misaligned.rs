@@ -0,0 +1,123 @@
+//! 模拟不对齐访存。
+//!
+//! 只模拟特权软件的不对齐访存。按字节重做一次访存，访存以陷入前的特权级进行；
+//! 重做时发生的异常交给特权软件处理。
+
+use crate::{
+    pmu, redirect,
+    riscv_spec::mepc,
+    unprivileged::{self, AccessFault},
+    xreg,
+};
+use fast_trap::{EntireContext, EntireResult};
+use riscv::register::mcause;
+
+/// 不对齐访存指令。
+#[derive(Clone, Copy, Debug)]
+enum Access {
+    Load {
+        rd: usize,
+        width: usize,
+        signed: bool,
+    },
+    Store {
+        rs2: usize,
+        width: usize,
+    },
+}
+
+/// 处理不对齐访存异常，参数是访存地址。
+pub(crate) extern "C" fn handler(ctx: EntireContext<usize>) -> EntireResult {
+    let (mut ctx, addr) = ctx.split();
+    let addr = addr.get();
+    let cause = mcause::read().bits();
+    let pc = mepc::read();
+
+    let result = unprivileged::load_insn(pc).and_then(|(insn, len)| {
+        match decode(insn) {
+            Some(Access::Load { rd, width, signed }) => {
+                pmu::firmware_event(pmu::fw::MISALIGNED_LOAD);
+                let val = load(addr, width)?;
+                let val = if signed { sign_extend(val, width) } else { val };
+                xreg::set(ctx.regs(), rd, val);
+            }
+            Some(Access::Store { rs2, width }) => {
+                pmu::firmware_event(pmu::fw::MISALIGNED_STORE);
+                store(addr, xreg::get(ctx.regs(), rs2), width)?;
+            }
+            // 浮点访存等不模拟，原样交给特权软件
+            None => return Err(AccessFault { cause, tval: addr }),
+        }
+        Ok(len)
+    });
+    match result {
+        Ok(len) => mepc::write(pc + len),
+        Err(fault) => redirect::to_supervisor(fault.cause, fault.tval),
+    }
+    ctx.restore()
+}
+
+/// 解码整数访存指令，包括压缩指令。
+fn decode(insn: u32) -> Option<Access> {
+    let insn = insn as usize;
+    let ld = |rd, width, signed| Some(Access::Load { rd, width, signed });
+    let st = |rs2, width| Some(Access::Store { rs2, width });
+
+    if insn & 0b11 == 0b11 {
+        let rd = (insn >> 7) & 0x1f;
+        let rs2 = (insn >> 20) & 0x1f;
+        match (insn & 0x7f, (insn >> 12) & 0b111) {
+            (0x03, 0b000) => ld(rd, 1, true),
+            (0x03, 0b001) => ld(rd, 2, true),
+            (0x03, 0b010) => ld(rd, 4, true),
+            (0x03, 0b011) => ld(rd, 8, false),
+            (0x03, 0b100) => ld(rd, 1, false),
+            (0x03, 0b101) => ld(rd, 2, false),
+            (0x03, 0b110) => ld(rd, 4, false),
+            (0x23, 0b000) => st(rs2, 1),
+            (0x23, 0b001) => st(rs2, 2),
+            (0x23, 0b010) => st(rs2, 4),
+            (0x23, 0b011) => st(rs2, 8),
+            _ => None,
+        }
+    } else {
+        // 压缩指令的 rd'/rs2' 只能是 x8..=x15
+        let rd_ = ((insn >> 2) & 0b111) + 8;
+        let rd = (insn >> 7) & 0x1f;
+        let rs2 = (insn >> 2) & 0x1f;
+        match (insn & 0b11, (insn >> 13) & 0b111) {
+            (0b00, 0b010) => ld(rd_, 4, true),            // c.lw
+            (0b00, 0b011) => ld(rd_, 8, false),           // c.ld
+            (0b00, 0b110) => st(rd_, 4),                  // c.sw
+            (0b00, 0b111) => st(rd_, 8),                  // c.sd
+            (0b10, 0b010) if rd != 0 => ld(rd, 4, true),  // c.lwsp
+            (0b10, 0b011) if rd != 0 => ld(rd, 8, false), // c.ldsp
+            (0b10, 0b110) => st(rs2, 4),                  // c.swsp
+            (0b10, 0b111) => st(rs2, 8),                  // c.sdsp
+            _ => None,
+        }
+    }
+}
+
+/// 按字节读，小端序。
+fn load(addr: usize, width: usize) -> Result<usize, AccessFault> {
+    let mut val = 0;
+    for i in (0..width).rev() {
+        val = val << 8 | unprivileged::load_u8(addr + i)? as usize;
+    }
+    Ok(val)
+}
+
+/// 按字节写，小端序。
+fn store(addr: usize, val: usize, width: usize) -> Result<(), AccessFault> {
+    for i in 0..width {
+        unprivileged::store_u8(addr + i, (val >> (i * 8)) as u8)?;
+    }
+    Ok(())
+}
+
+#[inline]
+fn sign_extend(val: usize, width: usize) -> usize {
+    let shift = usize::BITS as usize - width * 8;
+    ((val << shift) as isize >> shift) as usize
+}
@@ -0,0 +1,42 @@
+//! 写入 PMP。
+//!
+//! 条目由 [`common::pmp`] 规划，这里只负责写入寄存器。
+
+use common::pmp::Plan;
+use core::arch::asm;
+
+/// 写入 PMP，未使用的条目关闭。
+pub(crate) fn program(plan: &Plan) {
+    let mut cfg = [0usize; 2];
+    for (i, (c, addr)) in plan.entries().iter().enumerate() {
+        cfg[i / 8] |= (*c as usize) << (i % 8 * 8);
+        write_pmpaddr(i, *addr);
+    }
+    unsafe {
+        asm!("csrw pmpcfg0, {}", in(reg) cfg[0]);
+        asm!("csrw pmpcfg2, {}", in(reg) cfg[1]);
+    }
+}
+
+fn write_pmpaddr(i: usize, val: usize) {
+    use riscv::register::*;
+    match i {
+        0x0 => pmpaddr0::write(val),
+        0x1 => pmpaddr1::write(val),
+        0x2 => pmpaddr2::write(val),
+        0x3 => pmpaddr3::write(val),
+        0x4 => pmpaddr4::write(val),
+        0x5 => pmpaddr5::write(val),
+        0x6 => pmpaddr6::write(val),
+        0x7 => pmpaddr7::write(val),
+        0x8 => pmpaddr8::write(val),
+        0x9 => pmpaddr9::write(val),
+        0xa => pmpaddr10::write(val),
+        0xb => pmpaddr11::write(val),
+        0xc => pmpaddr12::write(val),
+        0xd => pmpaddr13::write(val),
+        0xe => pmpaddr14::write(val),
+        0xf => pmpaddr15::write(val),
+        _ => unreachable!(),
+    }
+}
@@ -0,0 +1,286 @@
+//! 性能监视单元。
+//!
+//! 计数器 0..18 对应 C906 的 `cycle`、`time`、`instret` 和 `hpmcounter3..=17`，
+//! 之后是 SEE 自己维护的固件计数器。
+
+use core::arch::asm;
+use rustsbi::spec::binary::SbiRet;
+
+const HW_COUNTERS: usize = 18;
+const FW_COUNTERS: usize = 8;
+pub(crate) const NUM_COUNTERS: usize = HW_COUNTERS + FW_COUNTERS;
+
+const CYCLE: usize = 0;
+const TIME: usize = 1;
+const INSTRET: usize = 2;
+
+const TYPE_HARDWARE: usize = 0;
+const TYPE_FIRMWARE: usize = 0xf;
+
+const HW_CPU_CYCLES: usize = 1;
+const HW_INSTRUCTIONS: usize = 2;
+
+/// 固件事件。
+pub(crate) mod fw {
+    pub const MISALIGNED_LOAD: usize = 0;
+    pub const MISALIGNED_STORE: usize = 1;
+    pub const ILLEGAL_INSN: usize = 4;
+    pub const SET_TIMER: usize = 5;
+    pub const IPI_SENT: usize = 6;
+    /// 平台定义的事件，RustSBI-D1 用它统计所有 SBI 调用。
+    pub const PLATFORM: usize = 0xffff;
+}
+
+/// SBI 事件到 C906 `mhpmevent` 编号的映射。
+const EVENT_MAP: [(usize, usize); 12] = [
+    (0x00003, 0x01), // CACHE_REFERENCES    : L1 ICache access
+    (0x00004, 0x02), // CACHE_MISSES        : L1 ICache miss
+    (0x00005, 0x07), // BRANCH_INSTRUCTIONS : conditional branch instruction
+    (0x00006, 0x06), // BRANCH_MISSES       : conditional branch mispredict
+    (0x10000, 0x0c), // L1D READ ACCESS
+    (0x10001, 0x0d), // L1D READ MISS
+    (0x10002, 0x0e), // L1D WRITE ACCESS
+    (0x10003, 0x0f), // L1D WRITE MISS
+    (0x10008, 0x01), // L1I READ ACCESS
+    (0x10009, 0x02), // L1I READ MISS
+    (0x10019, 0x04), // DTLB READ MISS      : D-uTLB miss
+    (0x10021, 0x03), // ITLB READ MISS      : I-uTLB miss
+];
+
+const CONFIG_SKIP_MATCH: usize = 1 << 0;
+const CONFIG_CLEAR_VALUE: usize = 1 << 1;
+const CONFIG_AUTO_START: usize = 1 << 2;
+const START_SET_INIT_VALUE: usize = 1 << 0;
+const STOP_RESET: usize = 1 << 0;
+
+struct State {
+    /// 每个计数器配置的事件，0 表示未配置。
+    events: [usize; NUM_COUNTERS],
+    /// 正在计数的计数器。
+    started: usize,
+    /// 固件计数器的值。
+    values: [u64; FW_COUNTERS],
+}
+
+static mut STATE: State = State {
+    events: [0; NUM_COUNTERS],
+    started: 0,
+    values: [0; FW_COUNTERS],
+};
+
+/// 向特权态开放计数器，停止所有可编程的计数器。
+pub(crate) fn init() {
+    const HPM_MASK: usize = ((1 << HW_COUNTERS) - 1) & !0b111;
+    unsafe {
+        asm!("csrw mcounteren, {}", in(reg) HPM_MASK | 1 << CYCLE | 1 << INSTRET);
+        asm!("csrs mcountinhibit, {}", in(reg) HPM_MASK);
+    }
+}
+
+/// 记录一次固件事件。
+pub(crate) fn firmware_event(code: usize) {
+    let state = unsafe { &mut STATE };
+    let event = TYPE_FIRMWARE << 16 | code;
+    for i in 0..FW_COUNTERS {
+        let idx = HW_COUNTERS + i;
+        if state.started & (1 << idx) != 0 && state.events[idx] == event {
+            state.values[i] += 1;
+        }
+    }
+}
+
+pub(crate) fn get_info(idx: usize) -> SbiRet {
+    if idx < HW_COUNTERS {
+        // 64 位的 `cycle + idx`
+        SbiRet::success(63 << 12 | (0xc00 + idx))
+    } else if idx < NUM_COUNTERS {
+        SbiRet::success(1 << (usize::BITS - 1))
+    } else {
+        SbiRet::invalid_param()
+    }
+}
+
+pub(crate) fn config_matching(base: usize, mask: usize, flags: usize, event: usize) -> SbiRet {
+    let Some(candidates) = counters(base, mask) else {
+        return SbiRet::invalid_param();
+    };
+    let state = unsafe { &mut STATE };
+    let found = if flags & CONFIG_SKIP_MATCH != 0 {
+        bits(candidates)
+            .next()
+            .and_then(|idx| selector(idx, event).map(|sel| (idx, sel)))
+    } else {
+        bits(candidates)
+            .filter(|idx| state.events[*idx] == 0)
+            .find_map(|idx| selector(idx, event).map(|sel| (idx, sel)))
+    };
+    let Some((idx, sel)) = found else {
+        return SbiRet::not_supported();
+    };
+    if idx >= HW_COUNTERS {
+        if flags & CONFIG_CLEAR_VALUE != 0 {
+            state.values[idx - HW_COUNTERS] = 0;
+        }
+    } else {
+        if idx > INSTRET {
+            write_event(idx, sel);
+        }
+        if flags & CONFIG_CLEAR_VALUE != 0 {
+            write_counter(idx, 0);
+        }
+    }
+    state.events[idx] = event;
+    if flags & CONFIG_AUTO_START != 0 && state.started & (1 << idx) == 0 {
+        start_one(state, idx);
+    }
+    SbiRet::success(idx)
+}
+
+pub(crate) fn start(base: usize, mask: usize, flags: usize, initial: u64) -> SbiRet {
+    let Some(counters) = counters(base, mask) else {
+        return SbiRet::invalid_param();
+    };
+    let state = unsafe { &mut STATE };
+    if bits(counters).any(|idx| state.events[idx] == 0) {
+        return SbiRet::invalid_param();
+    }
+    for idx in bits(counters) {
+        if state.started & (1 << idx) != 0 {
+            return SbiRet::already_started();
+        }
+        if flags & START_SET_INIT_VALUE != 0 {
+            if idx >= HW_COUNTERS {
+                state.values[idx - HW_COUNTERS] = initial;
+            } else {
+                write_counter(idx, initial as _);
+            }
+        }
+        start_one(state, idx);
+    }
+    SbiRet::success(0)
+}
+
+pub(crate) fn stop(base: usize, mask: usize, flags: usize) -> SbiRet {
+    let Some(counters) = counters(base, mask) else {
+        return SbiRet::invalid_param();
+    };
+    let state = unsafe { &mut STATE };
+    if bits(counters).any(|idx| state.events[idx] == 0) {
+        return SbiRet::invalid_param();
+    }
+    for idx in bits(counters) {
+        if state.started & (1 << idx) == 0 {
+            return SbiRet::already_stopped();
+        }
+        state.started &= !(1 << idx);
+        if idx < HW_COUNTERS {
+            unsafe { asm!("csrs mcountinhibit, {}", in(reg) 1 << idx) };
+        }
+        if flags & STOP_RESET != 0 {
+            state.events[idx] = 0;
+            if (INSTRET + 1..HW_COUNTERS).contains(&idx) {
+                write_event(idx, 0);
+            }
+        }
+    }
+    SbiRet::success(0)
+}
+
+pub(crate) fn fw_read(idx: usize) -> SbiRet {
+    if (HW_COUNTERS..NUM_COUNTERS).contains(&idx) {
+        SbiRet::success(unsafe { STATE.values[idx - HW_COUNTERS] } as _)
+    } else {
+        SbiRet::invalid_param()
+    }
+}
+
+#[inline]
+fn start_one(state: &mut State, idx: usize) {
+    state.started |= 1 << idx;
+    if idx < HW_COUNTERS {
+        unsafe { asm!("csrc mcountinhibit, {}", in(reg) 1 << idx) };
+    }
+}
+
+/// 计数器 `idx` 能否计数 `event`，能则返回要写入 `mhpmevent` 的值。
+fn selector(idx: usize, event: usize) -> Option<usize> {
+    let (ty, code) = (event >> 16, event & 0xffff);
+    match idx {
+        CYCLE if ty == TYPE_HARDWARE && code == HW_CPU_CYCLES => Some(0),
+        INSTRET if ty == TYPE_HARDWARE && code == HW_INSTRUCTIONS => Some(0),
+        CYCLE | TIME | INSTRET => None,
+        _ if idx < HW_COUNTERS => EVENT_MAP
+            .iter()
+            .find(|(sbi, _)| *sbi == event)
+            .map(|(_, hpm)| *hpm),
+        _ => {
+            use fw::*;
+            match (ty, code) {
+                (
+                    TYPE_FIRMWARE,
+                    MISALIGNED_LOAD | MISALIGNED_STORE | ILLEGAL_INSN | SET_TIMER | IPI_SENT
+                    | PLATFORM,
+                ) => Some(0),
+                _ => None,
+            }
+        }
+    }
+}
+
+/// 把 SBI 的计数器基址和掩码转换为计数器位图。
+#[inline]
+fn counters(base: usize, mask: usize) -> Option<usize> {
+    if mask == 0 {
+        return Some(0);
+    }
+    let last = base.checked_add((usize::BITS - 1 - mask.leading_zeros()) as _)?;
+    if last < NUM_COUNTERS {
+        Some(mask << base)
+    } else {
+        None
+    }
+}
+
+/// 依次取出位图中置位的序号。
+#[inline]
+fn bits(mut mask: usize) -> impl Iterator<Item = usize> {
+    core::iter::from_fn(move || {
+        if mask == 0 {
+            None
+        } else {
+            let i = mask.trailing_zeros() as usize;
+            mask &= mask - 1;
+            Some(i)
+        }
+    })
+}
+
+/// CSR 编号必须是立即数，为每个硬件计数器展开一次。
+macro_rules! hw_counter {
+    ($idx:expr, |$n:ident| $body:expr) => {
+        hw_counter!(@ $idx, $n, $body; 0 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17)
+    };
+    (@ $idx:expr, $n:ident, $body:expr; $($i:literal)+) => {
+        match $idx {
+            $($i => {
+                const $n: usize = $i;
+                $body
+            })+
+            _ => unreachable!(),
+        }
+    };
+}
+
+#[inline]
+fn write_event(idx: usize, val: usize) {
+    hw_counter!(idx, |N| unsafe {
+        asm!("csrw {csr}, {val}", csr = const 0x320 + N, val = in(reg) val)
+    })
+}
+
+#[inline]
+fn write_counter(idx: usize, val: usize) {
+    hw_counter!(idx, |N| unsafe {
+        asm!("csrw {csr}, {val}", csr = const 0xb00 + N, val = in(reg) val)
+    })
+}
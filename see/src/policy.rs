@@ -0,0 +1,57 @@
+//! 按启动策略设置平头哥扩展 CSR。
+
+use crate::riscv_spec::{mcor, mhcr, mxstatus};
+use common::policy::Policy;
+
+/// 一个策略对扩展 CSR 的设置。
+struct Settings {
+    /// `mhcr` 的值。
+    mhcr: usize,
+    /// `mxstatus` 中要置位的位。
+    set: usize,
+    /// `mxstatus` 中要清除的位。
+    clear: usize,
+}
+
+/// 开缓存、写分配写回、分支预测和写合并。
+const MHCR_ALL: usize =
+    mhcr::IE | mhcr::DE | mhcr::WA | mhcr::WB | mhcr::RS | mhcr::BPE | mhcr::BTB | mhcr::WBR;
+
+/// 策略表，保持 BROM 设置的策略不在表中。
+const fn settings(policy: Policy) -> Option<Settings> {
+    use mxstatus::*;
+    match policy {
+        Policy::Linux => Some(Settings {
+            mhcr: MHCR_ALL,
+            set: MM | MAEE | THEADISAEE,
+            clear: UCME | CLINTEE,
+        }),
+        Policy::Standard => Some(Settings {
+            mhcr: MHCR_ALL,
+            set: THEADISAEE,
+            clear: MM | MAEE | UCME | CLINTEE,
+        }),
+        Policy::Keep => None,
+    }
+}
+
+/// 应用启动策略。
+///
+/// 开缓存前写回并无效所有缓存，缓存可能已经被 BROM 或 SPL 打开。
+pub(crate) fn apply(policy: Policy) {
+    let Some(settings) = settings(policy) else {
+        return;
+    };
+    const OPS: usize =
+        mcor::SEL_ICACHE | mcor::SEL_DCACHE | mcor::INV | mcor::CLR | mcor::BHT_INV | mcor::BTB_INV;
+    mcor::write(OPS);
+    while mcor::read() & OPS & !(mcor::SEL_ICACHE | mcor::SEL_DCACHE) != 0 {
+        core::hint::spin_loop();
+    }
+    mhcr::write(settings.mhcr);
+    mxstatus::update(|bits| {
+        *bits &= !settings.clear;
+        *bits |= settings.set;
+    });
+    unsafe { core::arch::asm!("fence.i") };
+}
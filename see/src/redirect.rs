@@ -0,0 +1,27 @@
+//! 把异常转交给特权软件。
+
+use crate::riscv_spec::{mepc, mstatus};
+use core::arch::asm;
+
+/// 伪造一次特权软件直接收到的异常。
+///
+/// 按硬件陷入的方式设置 `scause`、`stval`、`sepc` 和 `sstatus`，返回后从 `stvec` 开始执行。
+pub(crate) fn to_supervisor(cause: usize, tval: usize) {
+    let stvec: usize;
+    unsafe {
+        asm!("csrw scause, {}", in(reg) cause);
+        asm!("csrw stval,  {}", in(reg) tval);
+        asm!("csrw sepc,   {}", in(reg) mepc::read());
+        asm!("csrr {}, stvec", out(reg) stvec);
+    }
+    mstatus::update(|bits| {
+        use mstatus::*;
+        // SPP <- 陷入前的特权级，SPIE <- SIE，SIE <- 0
+        let spp = if *bits & MPP == MPP_USER { 0 } else { SPP };
+        let spie = if *bits & SIE != 0 { SPIE } else { 0 };
+        *bits &= !(SPP | SPIE | SIE | MPP);
+        *bits |= spp | spie | MPP_SUPERVISOR;
+    });
+    // 异常总是进入向量表基址
+    mepc::write(stvec & !0b11);
+}
@@ -92,3 +92,97 @@ pub mod mepc {
         unsafe { asm!("csrw mepc, {}", in(reg) bits, options(nomem)) };
     }
 }
+
+/// 平头哥扩展状态寄存器。
+pub mod mxstatus {
+    use core::arch::asm;
+
+    /// 非对齐访问由硬件完成。
+    pub const MM: usize = 1 << 15;
+    /// U 态可以执行缓存维护指令。
+    pub const UCME: usize = 1 << 16;
+    /// S 态可以访问 CLINT 的定时器和软件中断。
+    pub const CLINTEE: usize = 1 << 17;
+    /// 页表项高位是平头哥扩展的页属性。
+    pub const MAEE: usize = 1 << 21;
+    /// 扩展指令使能。
+    pub const THEADISAEE: usize = 1 << 22;
+
+    pub fn update(f: impl FnOnce(&mut usize)) {
+        let mut bits = read();
+        f(&mut bits);
+        unsafe { asm!("csrw 0x7c0, {}", in(reg) bits, options(nomem)) };
+    }
+
+    #[inline(always)]
+    pub fn read() -> usize {
+        let bits: usize;
+        unsafe { asm!("csrr {}, 0x7c0", out(reg) bits, options(nomem)) };
+        bits
+    }
+}
+
+/// 平头哥硬件配置寄存器。
+pub mod mhcr {
+    use core::arch::asm;
+
+    /// 指令缓存使能。
+    pub const IE: usize = 1 << 0;
+    /// 数据缓存使能。
+    pub const DE: usize = 1 << 1;
+    /// 数据缓存写分配。
+    pub const WA: usize = 1 << 2;
+    /// 数据缓存写回。
+    pub const WB: usize = 1 << 3;
+    /// 返回地址栈使能。
+    pub const RS: usize = 1 << 4;
+    /// 分支预测使能。
+    pub const BPE: usize = 1 << 5;
+    /// 分支目标缓冲使能。
+    pub const BTB: usize = 1 << 6;
+    /// 连续写合并。
+    pub const WBR: usize = 1 << 8;
+
+    #[inline(always)]
+    pub fn read() -> usize {
+        let bits: usize;
+        unsafe { asm!("csrr {}, 0x7c1", out(reg) bits, options(nomem)) };
+        bits
+    }
+
+    #[inline(always)]
+    pub fn write(bits: usize) {
+        unsafe { asm!("csrw 0x7c1, {}", in(reg) bits, options(nomem)) };
+    }
+}
+
+/// 平头哥缓存操作寄存器。
+pub mod mcor {
+    use core::arch::asm;
+
+    /// 操作指令缓存。
+    pub const SEL_ICACHE: usize = 0b01;
+    /// 操作数据缓存。
+    pub const SEL_DCACHE: usize = 0b10;
+    /// 无效所选缓存。
+    pub const INV: usize = 1 << 4;
+    /// 写回所选缓存的脏行。
+    pub const CLR: usize = 1 << 5;
+    /// 无效分支历史表。
+    pub const BHT_INV: usize = 1 << 16;
+    /// 无效分支目标缓冲。
+    pub const BTB_INV: usize = 1 << 17;
+
+    #[inline(always)]
+    pub fn read() -> usize {
+        let bits: usize;
+        unsafe { asm!("csrr {}, 0x7c2", out(reg) bits) };
+        bits
+    }
+
+    /// 发起操作，操作完成后相应的位自动清零。
+    #[inline(always)]
+    pub fn write(bits: usize) {
+        unsafe { asm!("csrw 0x7c2, {}", in(reg) bits) };
+    }
+}
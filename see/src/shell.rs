@@ -0,0 +1,184 @@
+//! 启动特权软件前的命令行。
+//!
+//! 有内核时先倒计时，期间按任意键进入命令行；没有内核时直接进入。
+//! 命令行可以查看主存、PMP 和板信息，修改启动目标并继续启动。
+
+use crate::{hart_csr_utils, riscv_spec::mstatus, unprivileged, BoardInfo, Console};
+use core::ops::Range;
+use rcore_console::Console as _;
+
+/// 倒计时的秒数。
+const COUNTDOWN: usize = 3;
+const LINE_MAX: usize = 128;
+
+const HELP: &str = "\
+commands:
+  help                 show this message
+  md <addr> [len]      dump memory, 256 bytes by default
+  pmp                  print pmp entries
+  info                 show board information
+  kernel [addr]        show or change the boot target
+  boot                 continue boot
+  reset                reset the system";
+
+/// 倒计时，被打断或没有内核时进入命令行，返回要启动的内核的位置。
+///
+/// 命令行只接受 `available` 中的启动目标。
+pub(crate) fn countdown(
+    board: Option<&BoardInfo>,
+    kernel: Option<usize>,
+    available: Range<usize>,
+) -> usize {
+    if let Some(kernel) = kernel {
+        let freq = hal::ccu::HOSC.0 as usize;
+        let mut interrupted = false;
+        for left in (1..=COUNTDOWN).rev() {
+            print!("\r[rustsbi] press any key to enter shell, boot in {left}s ");
+            let deadline = riscv::register::time::read() + freq;
+            if wait_key(deadline) {
+                interrupted = true;
+                break;
+            }
+        }
+        println!();
+        if !interrupted {
+            return kernel;
+        }
+    } else {
+        println!("[rustsbi] no kernel, enter shell");
+    }
+    run(board, kernel, available)
+}
+
+/// 在截止时间前有按键时返回 `true`。
+fn wait_key(deadline: usize) -> bool {
+    while riscv::register::time::read() < deadline {
+        if Console.get_char().is_some() {
+            return true;
+        }
+        core::hint::spin_loop();
+    }
+    false
+}
+
+/// 执行命令，直到 `boot` 命令给出启动目标。
+fn run(board: Option<&BoardInfo>, mut kernel: Option<usize>, available: Range<usize>) -> usize {
+    // 访存以 `mstatus.MPP` 的特权级进行，启动前用 M 态
+    mstatus::update(|bits| *bits |= mstatus::MPP_MACHINE);
+    let mut line = [0u8; LINE_MAX];
+    loop {
+        print!("rustsbi> ");
+        let len = read_line(&mut line);
+        let line = unsafe { core::str::from_utf8_unchecked(&line[..len]) };
+        let mut args = line.split_whitespace();
+        match args.next() {
+            None => {}
+            Some("help") => println!("{HELP}"),
+            Some("md") => match args.next().and_then(parse_addr) {
+                Some(addr) => dump(addr, args.next().and_then(parse_addr).unwrap_or(256)),
+                None => println!("usage: md <addr> [len]"),
+            },
+            Some("pmp") => hart_csr_utils::print_pmps(),
+            Some("info") => match board {
+                Some(board) => {
+                    println!("model:  {}", board.model.as_str());
+                    println!("memory: {:#x?}", board.mem);
+                    println!("dtb:    {:#x?}", board.dtb);
+                }
+                None => println!("no board information"),
+            },
+            Some("kernel") => match args.next().map(parse_addr) {
+                None => match kernel {
+                    Some(addr) => println!("boot target: {addr:#x}"),
+                    None => println!("no boot target"),
+                },
+                // 固件之下的位置会被 PMP 禁止，之上不能越过主存、设备树和初始内存盘
+                Some(Some(addr)) if available.contains(&addr) => kernel = Some(addr),
+                Some(Some(addr)) if addr < available.start => {
+                    println!("{addr:#x} overlaps firmware")
+                }
+                Some(Some(addr)) => println!(
+                    "{addr:#x} is beyond available memory below {:#x}",
+                    available.end
+                ),
+                Some(None) => println!("usage: kernel [addr]"),
+            },
+            Some("boot") => match kernel {
+                Some(addr) => break addr,
+                None => println!("no boot target, set one with `kernel <addr>`"),
+            },
+            Some("reset") => hal::wdt::reset_system(),
+            Some(cmd) => println!("unknown command `{cmd}`, try `help`"),
+        }
+    }
+}
+
+/// 读一行，回显输入并处理退格，返回行的长度。
+fn read_line(buf: &mut [u8]) -> usize {
+    let mut len = 0;
+    loop {
+        let Some(c) = Console.get_char() else {
+            core::hint::spin_loop();
+            continue;
+        };
+        match c {
+            b'\r' | b'\n' => {
+                println!();
+                return len;
+            }
+            // 退格
+            0x08 | 0x7f if len > 0 => {
+                len -= 1;
+                print!("\x08 \x08");
+            }
+            c if c.is_ascii_graphic() || c == b' ' => {
+                if len < buf.len() {
+                    buf[len] = c;
+                    len += 1;
+                    Console.put_char(c);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// 以十六进制和 ASCII 打印主存，每行 16 字节。
+fn dump(addr: usize, len: usize) {
+    let stop = addr.saturating_add(len);
+    for line in (addr..stop).step_by(16) {
+        let end = line.saturating_add(16).min(stop);
+        let mut bytes = [0u8; 16];
+        for (i, a) in (line..end).enumerate() {
+            match unprivileged::load_u8(a) {
+                Ok(b) => bytes[i] = b,
+                Err(_) => {
+                    println!("{a:#010x}: access fault");
+                    return;
+                }
+            }
+        }
+        print!("{line:#010x}:");
+        for b in &bytes[..end - line] {
+            print!(" {b:02x}");
+        }
+        print!("{:width$}  ", "", width = (16 - (end - line)) * 3);
+        for b in &bytes[..end - line] {
+            let c = if b.is_ascii_graphic() {
+                *b as char
+            } else {
+                '.'
+            };
+            print!("{c}");
+        }
+        println!();
+    }
+}
+
+/// 解析十六进制或十进制数。
+fn parse_addr(s: &str) -> Option<usize> {
+    match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
@@ -0,0 +1,53 @@
+//! 机器态定时器。
+//!
+//! 定时器的行为在 `common` 中实现，这里提供 C906 的 CLINT 和 `mip`。
+
+use common::timer::{Clint, Timer};
+use hal::CLINT_BASE;
+use riscv::register::{mip, time};
+
+/// `mtimecmp` 相对 CLINT 的偏移。
+pub(crate) const MTIMECMP: usize = 0x4000;
+
+/// 已设置而尚未触发的定时器。
+///
+/// 定时器中断代理触发时清除对应的位。
+pub(crate) static TIMER: Timer = Timer::new();
+
+struct Hardware;
+
+impl Clint for Hardware {
+    #[inline]
+    fn time(&self) -> u64 {
+        time::read() as _
+    }
+
+    #[inline]
+    fn write_mtimecmp(&self, hartid: usize, high: bool, val: u32) {
+        let mtimecmp = (CLINT_BASE + MTIMECMP + hartid * 8) as *mut u32;
+        unsafe { mtimecmp.add(high as usize).write_volatile(val) };
+    }
+
+    #[inline]
+    fn set_stip(&self, pending: bool) {
+        if pending {
+            unsafe { mip::set_stimer() };
+        } else {
+            unsafe { mip::clear_stimer() };
+        }
+    }
+}
+
+/// 为 `hartid` 设置下一次定时器中断，并清除已挂起的 stip。
+///
+/// 设置一个已经过去的时间会立即挂起 stip。
+#[inline]
+pub(crate) fn set(hartid: usize, stime_value: u64) {
+    TIMER.set(&Hardware, hartid, stime_value);
+}
+
+/// 定时器是否已设置而尚未触发。
+#[inline]
+pub(crate) fn armed(hartid: usize) -> bool {
+    TIMER.armed(hartid)
+}
@@ -57,12 +57,22 @@ unsafe extern "C" fn mtimer() {
         "   sd    a0, -1*8(sp)
             sd    a1, -2*8(sp)
         ",
-        // 清除 mtimecmp
-        "   li    a0, {clint} + 0x4000
+        // 清除当前硬件线程的 mtimecmp
+        "   csrr  a1, mhartid
+            slli  a1, a1, 3
+            li    a0, {clint} + {mtimecmp}
+            add   a0, a0, a1
             addi  a1, zero, -1
-            sw    a1, (a0)
-            addi  a0, a0, 4
-            sw    a1, (a0)
+            sw    a1, 4(a0)
+            sw    a1, 0(a0)
+        ",
+        // 清除已设置标记
+        "   csrr  a1, mhartid
+            li    a0, 1
+            sll   a0, a0, a1
+            not   a0, a0
+            la    a1, {armed}
+            amoand.d zero, a0, (a1)
         ",
         // 设置 stip
         "   li    a0, {mip_stip}
@@ -80,6 +90,8 @@ unsafe extern "C" fn mtimer() {
         "   mret",
         mip_stip = const 1 << 5,
         clint    = const CLINT_BASE,
+        mtimecmp = const crate::timer::MTIMECMP,
+        armed    =   sym crate::timer::TIMER,
         options(noreturn)
     )
 }
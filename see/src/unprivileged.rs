@@ -0,0 +1,129 @@
+//! 以陷入前的特权级访问内存。
+//!
+//! 访问时设置 `mstatus.MPRV`，地址转换和保护都与陷入前的特权软件一致。
+//! 访问引发的异常由临时的陷入向量接住，作为 `Err` 返回而不会进入 SEE 的陷入处理。
+
+use crate::riscv_spec::{mepc, mstatus};
+use core::arch::asm;
+use riscv::register::mtval;
+
+/// 访存时发生的异常。
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct AccessFault {
+    pub cause: usize,
+    pub tval: usize,
+}
+
+#[repr(C)]
+struct Ret {
+    value: usize,
+    cause: usize,
+}
+
+const NO_TRAP: usize = usize::MAX;
+
+const EXC_INST_ACCESS_FAULT: usize = 1;
+const EXC_LOAD_ACCESS_FAULT: usize = 5;
+const EXC_INST_PAGE_FAULT: usize = 12;
+const EXC_LOAD_PAGE_FAULT: usize = 13;
+
+/// 读一个字节。
+#[inline]
+pub(crate) fn load_u8(addr: usize) -> Result<u8, AccessFault> {
+    access(|| unsafe { raw_lbu(addr) }).map(|b| b as _)
+}
+
+/// 写一个字节。
+#[inline]
+pub(crate) fn store_u8(addr: usize, val: u8) -> Result<(), AccessFault> {
+    access(|| unsafe { raw_sb(addr, val as _) }).map(|_| ())
+}
+
+/// 读 `addr` 处的一条指令，返回指令和指令长度。
+///
+/// 按指令访问报告异常。
+pub(crate) fn load_insn(addr: usize) -> Result<(u32, usize), AccessFault> {
+    let fetch = |addr| {
+        access(|| unsafe { raw_lhu_mxr(addr) }).map_err(|mut e| {
+            e.cause = match e.cause {
+                EXC_LOAD_ACCESS_FAULT => EXC_INST_ACCESS_FAULT,
+                EXC_LOAD_PAGE_FAULT => EXC_INST_PAGE_FAULT,
+                cause => cause,
+            };
+            e
+        })
+    };
+    let lo = fetch(addr)? as u32;
+    if lo & 0b11 != 0b11 {
+        Ok((lo, 2))
+    } else {
+        let hi = fetch(addr + 2)? as u32;
+        Ok((lo | hi << 16, 4))
+    }
+}
+
+/// 异常会改写 `mepc`，访问后恢复。
+#[inline]
+fn access(f: impl FnOnce() -> Ret) -> Result<usize, AccessFault> {
+    let pc = mepc::read();
+    let ret = f();
+    let ans = if ret.cause == NO_TRAP {
+        Ok(ret.value)
+    } else {
+        Err(AccessFault {
+            cause: ret.cause,
+            tval: mtval::read(),
+        })
+    };
+    mepc::write(pc);
+    ans
+}
+
+/// 生成一个带异常保护的访存函数。
+///
+/// 访存指令不能压缩，临时陷入向量跳过 4 字节。
+macro_rules! guarded {
+    ($name:ident($($arg:ident: $ty:ty),*), $flags:expr, $insn:literal) => {
+        #[naked]
+        unsafe extern "C" fn $name($($arg: $ty),*) -> Ret {
+            asm!(
+                ".option push",
+                ".option norvc",
+                "   mv    t2, a1
+                    li    a1, -1
+                    la    t0, 1f
+                    csrrw t0, mtvec, t0
+                    li    t1, {flags}
+                    csrrs t1, mstatus, t1
+                ",
+                $insn,
+                "   csrw  mstatus, t1
+                    csrw  mtvec, t0
+                    ret
+                ",
+                ".align 2",
+                "1: csrr  a1, mcause
+                    csrr  a2, mepc
+                    addi  a2, a2, 4
+                    csrw  mepc, a2
+                    mret
+                ",
+                ".option pop",
+                flags = const $flags,
+                options(noreturn)
+            )
+        }
+    };
+}
+
+guarded!(raw_lbu(addr: usize), mstatus::MPRV, "lbu a0, 0(a0)");
+guarded!(
+    raw_lhu_mxr(addr: usize),
+    mstatus::MPRV | mstatus::MXR,
+    "lhu a0, 0(a0)"
+);
+guarded!(
+    raw_sb(addr: usize, val: usize),
+    mstatus::MPRV,
+    "sb t2, 0(a0)"
+);
@@ -0,0 +1,119 @@
+//! 基于帧指针回溯调用栈。
+//!
+//! SEE 以帧指针构建，每个栈帧的 `s0` 指向调用者的栈顶，`s0 - 8` 处保存返回地址，
+//! `s0 - 16` 处保存调用者的 `s0`。符号表由 `build.rs` 从上一次构建的结果生成。
+
+use crate::{ROOT_STACK, STACK_SIZE};
+use core::{ops::Range, ptr::addr_of};
+
+#[used]
+#[link_section = ".symbols"]
+static SYMBOLS: [u8; include_bytes!(concat!(env!("OUT_DIR"), "/symbols.bin")).len()] =
+    *include_bytes!(concat!(env!("OUT_DIR"), "/symbols.bin"));
+
+/// 从 `pc` 开始沿 `s0` 链回溯，依次产生 `pc`、`ra` 和各帧的返回地址。
+///
+/// 帧指针越出启动栈、没有对齐或返回地址不在 `.text` 中时停止。
+pub(crate) fn walk(pc: usize, ra: usize, mut fp: usize) -> impl Iterator<Item = usize> {
+    let stack = unsafe { addr_of!(ROOT_STACK) as usize };
+    let stack = stack + 16..stack + STACK_SIZE + 1;
+    let text = text();
+    let mut first = [pc, ra].into_iter().filter(|addr| *addr != 0);
+    let mut last = 0;
+    core::iter::from_fn(move || loop {
+        let addr = match first.next() {
+            Some(addr) => addr,
+            None => {
+                if !stack.contains(&fp) || fp % 8 != 0 {
+                    return None;
+                }
+                let (ra, prev) =
+                    unsafe { (*((fp - 8) as *const usize), *((fp - 16) as *const usize)) };
+                if !text.contains(&ra) || prev <= fp {
+                    return None;
+                }
+                fp = prev;
+                ra
+            }
+        };
+        // 叶函数不保存 `ra`，第一帧的返回地址可能和 `ra` 重复
+        if addr != last {
+            last = addr;
+            return Some(addr);
+        }
+    })
+}
+
+/// 打印回溯到的地址和所在的函数。
+pub(crate) fn print(frames: &[usize]) {
+    println!("[rustsbi] backtrace:");
+    for (i, addr) in frames.iter().enumerate() {
+        match symbolize(*addr) {
+            Some((name, offset)) => println!("  #{i:<2} {addr:#010x} {name}+{offset:#x}"),
+            None => println!("  #{i:<2} {addr:#010x} <unknown>"),
+        }
+    }
+}
+
+/// 查找地址所在的函数，返回函数名和地址在函数中的偏移。
+pub(crate) fn symbolize(addr: usize) -> Option<(&'static str, usize)> {
+    let table = symbols();
+    let word = |i: usize| {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(table.get(i * 4..i * 4 + 4)?);
+        Some(u32::from_le_bytes(bytes) as usize)
+    };
+    let n = word(0)?;
+    let entry = |i: usize| -> Option<[usize; 4]> {
+        let base = 1 + i * 4;
+        Some([
+            word(base)?,
+            word(base + 1)?,
+            word(base + 2)?,
+            word(base + 3)?,
+        ])
+    };
+    // 最后一个起点不大于 `addr` 的符号
+    let (mut lo, mut hi) = (0, n);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        if entry(mid)?[0] <= addr {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    let [start, size, name, len] = entry(lo.checked_sub(1)?)?;
+    if addr >= start + size.max(1) {
+        return None;
+    }
+    let names = (1 + n * 4) * 4;
+    let name = table.get(names + name..names + name + len)?;
+    Some((core::str::from_utf8(name).ok()?, addr - start))
+}
+
+/// 固件的代码段。
+#[inline]
+fn text() -> Range<usize> {
+    extern "C" {
+        fn stext();
+        fn etext();
+    }
+    stext as usize..etext as usize
+}
+
+/// 链接到固件中的符号表。
+///
+/// 通过链接脚本中的符号访问，避免代码依赖符号表的长度。
+#[inline]
+fn symbols() -> &'static [u8] {
+    extern "C" {
+        static ssymbols: u8;
+        static esymbols: u8;
+    }
+    unsafe {
+        let start = addr_of!(ssymbols);
+        let end = addr_of!(esymbols);
+        core::slice::from_raw_parts(start, end as usize - start as usize)
+    }
+}
@@ -0,0 +1,45 @@
+//! 按编号访问陷入上下文中的通用寄存器。
+
+use fast_trap::FlowContext;
+
+/// 读 `x{i}`。
+pub(crate) fn get(ctx: &FlowContext, i: usize) -> usize {
+    match i {
+        0 => 0,
+        1 => ctx.ra,
+        2 => ctx.sp,
+        3 => ctx.gp,
+        4 => ctx.tp,
+        5..=7 => ctx.t[i - 5],
+        8..=9 => ctx.s[i - 8],
+        10..=17 => ctx.a[i - 10],
+        18..=27 => ctx.s[i - 16],
+        28..=31 => ctx.t[i - 25],
+        _ => panic!("invalid register: x{i}"),
+    }
+}
+
+/// 写 `x{i}`，写 `x0` 无效。
+pub(crate) fn set(ctx: &mut FlowContext, i: usize, val: usize) {
+    match i {
+        0 => {}
+        1 => ctx.ra = val,
+        2 => ctx.sp = val,
+        3 => ctx.gp = val,
+        4 => ctx.tp = val,
+        5..=7 => ctx.t[i - 5] = val,
+        8..=9 => ctx.s[i - 8] = val,
+        10..=17 => ctx.a[i - 10] = val,
+        18..=27 => ctx.s[i - 16] = val,
+        28..=31 => ctx.t[i - 25] = val,
+        _ => panic!("invalid register: x{i}"),
+    }
+}
+
+/// 快速路径不保存的寄存器，即被调用者保存的 `s0..=s11`。
+///
+/// 修改这些寄存器要转到完整路径。
+#[inline]
+pub(crate) const fn lazy(i: usize) -> bool {
+    matches!(i, 8 | 9 | 18..=27)
+}
@@ -8,7 +8,7 @@ mod magic;
 
 use common::{
     flash::{Meta as FlashMeta, META as META_POS},
-    memory::{dtb_offset, parse_memory_size, Meta as MemMeta, DRAM, KERNEL},
+    memory::{dtb_offset, initrd_offset, Meta as MemMeta, BOOTARGS, BOOTARGS_MAX, DRAM, KERNEL},
     AsBinary, EgonHead,
 };
 use core::ptr::addr_of;
@@ -152,6 +152,10 @@ extern "C" fn main() -> usize {
         }
     };
     let _ = Out << LOGO << Endl;
+    // 探测主存大小
+    let dram_size = probe_dram_size();
+    unsafe { META.set_dram_size(dram_size) };
+    let _ = Out << "DRAM size: " << (dram_size >> 20) << " MiB" << Endl;
     // 如果不是从 flash 引导的，直接按照 dram 放好的位置跳
     let meta = unsafe { addr_of!(META).read_volatile() };
     if !meta.from_flash {
@@ -198,14 +202,39 @@ extern "C" fn main() -> usize {
         None => arrow_walk(),
     };
 
+    // 传递启动策略
+    if let Some(policy) = meta.policy() {
+        unsafe { META.set_policy(policy) };
+    }
     // 拷贝 dtb
     if let Some((pos, len)) = meta.dtb() {
         let _ = log_loading("dtb", pos, len);
         flash.copy_into(pos, unsafe { static_buf(DRAM, len) });
-        let offset = dtb_offset(parse_memory_size(DRAM as _));
+        let offset = dtb_offset(dram_size);
         unsafe { META.dtb = offset };
         let dst = (DRAM as u32 + offset) as *mut u8;
         unsafe { dst.copy_from_nonoverlapping(DRAM as *const u8, len) };
+        // 拷贝 initrd，放在 dtb 之前，不能覆盖 kernel
+        if let Some((pos, len)) = meta.initrd() {
+            let _ = log_loading("initrd", pos, len);
+            let kernel_end = KERNEL + meta.kernel().map_or(0, |(_, len)| len);
+            let initrd = DRAM + initrd_offset(offset, len as _) as usize;
+            if initrd < kernel_end {
+                let _ = Out << "initrd overlaps kernel, skipped" << Endl;
+            } else {
+                flash.copy_into(pos, unsafe { static_buf(initrd, len) });
+                unsafe { META.initrd = len as _ };
+            }
+        }
+    }
+    // 拷贝启动参数，保证以 0 结尾
+    if let Some((pos, len)) = meta.bootargs() {
+        let _ = log_loading("bootargs", pos, len);
+        let len = len.min(BOOTARGS_MAX - 1);
+        let buf = unsafe { static_buf(BOOTARGS, len + 1) };
+        flash.copy_into(pos, &mut buf[..len]);
+        buf[len] = 0;
+        unsafe { META.bootargs = (BOOTARGS - DRAM) as _ };
     }
     // 拷贝 see
     let _ = log_loading("see", see_pos, see_len);
@@ -235,6 +264,34 @@ fn panic(_info: &PanicInfo) -> ! {
     }
 }
 
+/// 利用地址回绕探测主存大小。
+///
+/// 超出实际容量的地址会回绕到主存开头，从 64 MiB 开始逐次加倍，直到写入的值出现在主存开头。
+fn probe_dram_size() -> usize {
+    const MIN: usize = 64 << 20;
+    const MAX: usize = 2 << 30;
+    const MARK: u32 = 0x5a5a_a5a5;
+    let base = DRAM as *mut u32;
+    let mut size = MIN;
+    unsafe {
+        let saved = base.read_volatile();
+        base.write_volatile(!MARK);
+        while size < MAX {
+            let probe = (DRAM + size) as *mut u32;
+            let old = probe.read_volatile();
+            probe.write_volatile(MARK);
+            let aliased = base.read_volatile() == MARK;
+            probe.write_volatile(old);
+            if aliased {
+                break;
+            }
+            size <<= 1;
+        }
+        base.write_volatile(saved);
+    }
+    size
+}
+
 #[inline]
 unsafe fn static_buf(base: usize, size: usize) -> &'static mut [u8] {
     core::slice::from_raw_parts_mut(base as *mut u8, size)
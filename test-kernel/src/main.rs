@@ -5,7 +5,7 @@
 #![feature(naked_functions, asm_const)]
 
 use core::arch::asm;
-use hal::pac::UART0;
+use hal::{pac::Peripherals, uart::Uart};
 use riscv::register::*;
 use sbi_testing::sbi;
 
@@ -161,11 +161,7 @@ struct Console;
 impl rcore_console::Console for Console {
     #[inline]
     fn put_char(&self, c: u8) {
-        let uart = unsafe { &*UART0::ptr() };
-        // 等待 FIFO 空位
-        while uart.usr.read().tfnf().is_full() {
-            core::hint::spin_loop();
-        }
-        uart.thr().write(|w| w.thr().variant(c));
+        // SEE 已经配置好 UART0
+        Uart::attach(unsafe { Peripherals::steal() }.UART0).write_byte(c);
     }
 }
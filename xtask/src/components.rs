@@ -18,6 +18,38 @@ pub(crate) struct Components {
     kernel: Option<PathBuf>,
     #[clap(long, global = true)]
     dt: Option<PathBuf>,
+    /// kernel command line, overrides `/chosen/bootargs` in the device tree
+    #[clap(long, global = true)]
+    bootargs: Option<String>,
+    /// initramfs passed to the kernel through `/chosen`
+    #[clap(long, global = true)]
+    initrd: Option<PathBuf>,
+    /// build see with a gdb stub on UART0, entered on panic or M-mode ebreak
+    #[clap(long, global = true)]
+    gdb_stub: bool,
+    /// how see configures the C906 extension CSRs, kept unchanged in flash if not given
+    #[clap(long, global = true, value_enum)]
+    policy: Option<BootPolicy>,
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+enum BootPolicy {
+    /// caches, MAEE page attributes, T-Head instructions and unaligned access
+    Linux,
+    /// caches and T-Head instructions, standard page table entries
+    Standard,
+    /// leave the settings of BROM
+    Keep,
+}
+
+impl From<BootPolicy> for common::policy::Policy {
+    fn from(value: BootPolicy) -> Self {
+        match value {
+            BootPolicy::Linux => Self::Linux,
+            BootPolicy::Standard => Self::Standard,
+            BootPolicy::Keep => Self::Keep,
+        }
+    }
 }
 
 impl Components {
@@ -41,12 +73,12 @@ impl Components {
         }
         // 生成 see
         if self.see {
-            ans.see.replace(Package::See.objcopy());
+            ans.see.replace(self.see_package().objcopy());
         }
         // 生成 kernel
         if let Some(kernel) = &self.kernel {
             if kernel.as_os_str() == OsStr::new("::test") {
-                ans.kernel.replace(Package::TestKernel.objcopy());
+                ans.kernel.replace(Package::TestKernel.elf());
             } else {
                 // 检查 kernel 文件是否存在
                 if !kernel.is_file() {
@@ -80,19 +112,47 @@ impl Components {
                 ans.dtb.replace(dt.clone());
             }
         }
+        // 检查 initrd
+        if let Some(initrd) = &self.initrd {
+            if !initrd.is_file() {
+                return Err(IoError::new(
+                    IoErrorKind::NotFound,
+                    format!("initrd file \"{}\" not exist", initrd.display()),
+                )
+                .into());
+            }
+            ans.initrd.replace(initrd.clone());
+        }
+        // 生成启动参数，以 0 结尾
+        if let Some(bootargs) = &self.bootargs {
+            if bootargs.len() >= common::memory::BOOTARGS_MAX {
+                return Err(XError::InvalidProcedure("bootargs too long".into()));
+            }
+            let path = DIRS.target.join("bootargs.bin");
+            dir::create_parent(&path).unwrap();
+            fs::write(&path, [bootargs.as_bytes(), &[0]].concat())?;
+            ans.bootargs.replace(path);
+        }
         Ok(ans)
     }
 
+    #[inline]
+    fn see_package(&self) -> Package {
+        Package::See {
+            gdb_stub: self.gdb_stub,
+        }
+    }
+
     pub fn asm(&self, arg: AsmArg) -> Result<(), XError> {
         let mut packages = vec![];
         if self.spl {
             packages.push(Package::Spl);
         }
         if self.see {
-            packages.push(Package::See);
+            packages.push(self.see_package());
         }
         let packages = if packages.is_empty() {
-            vec![Package::Spl, Package::See]
+            vec![Package::Spl, self.see_package()]
         } else {
             packages
         };
@@ -149,6 +209,9 @@ impl Components {
         // 生成
         let target = self.make()?;
         let mut meta = Meta::DEFAULT;
+        if let Some(policy) = self.policy {
+            meta.set_policy(policy.into());
+        }
         // 写入 see
         if let Some(see) = &target.see {
             Xfel::ddr("d1").invoke();
@@ -175,6 +238,34 @@ impl Components {
                 meta.set_dtb(offset);
                 info!("write {} to {address:#x}", dtb.display());
                 Xfel::write(address, dtb).invoke();
+                // 写入 initrd，放在 dtb 之前，不能覆盖 kernel
+                if let Some(initrd) = &target.initrd {
+                    let len = initrd.metadata().unwrap().len() as u32;
+                    let address = DRAM + initrd_offset(offset, len) as usize;
+                    let kernel_end = KERNEL
+                        + target
+                            .kernel
+                            .as_ref()
+                            .map_or(0, |kernel| kernel.metadata().unwrap().len() as usize);
+                    if address < kernel_end {
+                        return Err(XError::InvalidProcedure(format!(
+                            "initrd at {address:#x} overlaps kernel ending at {kernel_end:#x}"
+                        )));
+                    }
+                    meta.set_initrd(len);
+                    info!("write {} to {address:#x}", initrd.display());
+                    Xfel::write(address, initrd).invoke();
+                }
+            } else if target.initrd.is_some() {
+                return Err(XError::InvalidProcedure(
+                    "cannot debug initrd without dtb".into(),
+                ));
+            }
+            // 写入启动参数
+            if let Some(bootargs) = &target.bootargs {
+                meta.set_bootargs((BOOTARGS - DRAM) as _);
+                info!("write {} to {BOOTARGS:#x}", bootargs.display());
+                Xfel::write(BOOTARGS, bootargs).invoke();
             }
         }
         // 写入 spl 或执行外部初始化流程
@@ -230,6 +321,9 @@ impl Components {
             Xfel::spinand_read(META as _, Meta::SIZE, &meta_path).invoke();
             File::open(&meta_path)?.read_exact(meta.as_buf())?;
         }
+        if let Some(policy) = self.policy {
+            meta.set_policy(policy.into());
+        }
         // 写各模块
         if let Some(see) = target.see {
             meta.set_see(SEE, see.metadata().unwrap().len() as _);
@@ -243,6 +337,14 @@ impl Components {
             meta.set_dtb(DTB, dtb.metadata().unwrap().len() as _);
             Xfel::spinand_write(DTB as _, dtb).invoke();
         }
+        if let Some(initrd) = target.initrd {
+            meta.set_initrd(INITRD, initrd.metadata().unwrap().len() as _);
+            Xfel::spinand_write(INITRD as _, initrd).invoke();
+        }
+        if let Some(bootargs) = target.bootargs {
+            meta.set_bootargs(BOOTARGS, bootargs.metadata().unwrap().len() as _);
+            Xfel::spinand_write(BOOTARGS as _, bootargs).invoke();
+        }
         // 元数据写到文件，再从文件写到 flash
         fs::write(&meta_path, meta.as_bytes()).unwrap();
         Xfel::spinand_write(META as _, meta_path).invoke();
@@ -74,7 +74,7 @@ impl Dirs {
 
 enum Package {
     Spl,
-    See,
+    See { gdb_stub: bool },
     TestKernel,
 }
 
@@ -83,15 +83,52 @@ impl Package {
     const fn name(&self) -> &'static str {
         match self {
             Self::Spl => "spl",
-            Self::See => "see",
+            Self::See { .. } => "see",
             Self::TestKernel => "test-kernel",
         }
     }
 
-    #[inline]
     fn build(&self) {
         info!("build `{}`", self.name());
-        Cargo::build().package(self.name()).release().invoke();
+        match self {
+            // see 的符号表来自上一次构建的结果，符号变化时再构建一次
+            Self::See { gdb_stub } => {
+                let symbols = DIRS.target.join("see.symbols");
+                let build = || {
+                    let mut cargo = Cargo::build();
+                    cargo
+                        .package(self.name())
+                        .release()
+                        .env("SEE_SYMBOLS", &symbols);
+                    if *gdb_stub {
+                        cargo.args(["--features", "gdb-stub"]);
+                    }
+                    cargo.invoke();
+                };
+                build();
+                let dump = BinUtil::objdump()
+                    .arg(self.target())
+                    .args(["-t", "-C"])
+                    .output()
+                    .stdout;
+                if std::fs::read(&symbols).ok().as_ref() != Some(&dump) {
+                    info!("embed symbols of `{}`", self.name());
+                    std::fs::write(&symbols, &dump).unwrap();
+                    build();
+                    // 嵌入的符号表必须和最终的二进制一致
+                    let check = BinUtil::objdump()
+                        .arg(self.target())
+                        .args(["-t", "-C"])
+                        .output()
+                        .stdout;
+                    if check != dump {
+                        error!("symbols of `{}` moved after embedding", self.name());
+                        std::process::exit(1);
+                    }
+                }
+            }
+            _ => Cargo::build().package(self.name()).release().invoke(),
+        }
     }
 
     #[inline]
@@ -114,6 +151,20 @@ impl Package {
         Ok(())
     }
 
+    /// 保留 ELF 格式，只去掉符号和调试信息。
+    fn elf(&self) -> PathBuf {
+        self.build();
+        let target = self.target();
+        let elf = target.with_extension("elf");
+        info!("strip `{}` to {}", self.name(), elf.display());
+        BinUtil::objcopy()
+            .arg(target)
+            .arg("--strip-all")
+            .arg(&elf)
+            .invoke();
+        elf
+    }
+
     fn objcopy(&self) -> PathBuf {
         self.build();
         let target = self.target();
@@ -135,6 +186,8 @@ struct Target {
     see: Option<PathBuf>,
     kernel: Option<PathBuf>,
     dtb: Option<PathBuf>,
+    bootargs: Option<PathBuf>,
+    initrd: Option<PathBuf>,
 }
 
 #[derive(Args)]